@@ -1,7 +1,8 @@
 use crate::ptrace_control::*;
+use nix::sys::signal::Signal;
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
 use nix::unistd::Pid;
 use nix::{Error, Result};
-use std::collections::HashMap;
 
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 const INT: u64 = 0xCC;
@@ -19,8 +20,6 @@ pub struct Breakpoint {
     /// Reading from memory with ptrace gives addresses aligned to bytes.
     /// We therefore need to know the shift to place the breakpoint in the right place
     shift: u64,
-    /// Map of the state of the breakpoint on each thread/process
-    is_running: HashMap<Pid, bool>,
 }
 
 impl Breakpoint {
@@ -31,12 +30,7 @@ impl Breakpoint {
         let shift = 8 * (pc - aligned);
         let data = ((data >> shift) & 0xFF) as u8;
 
-        let mut b = Breakpoint {
-            pc,
-            data,
-            shift,
-            is_running: HashMap::new(),
-        };
+        let mut b = Breakpoint { pc, data, shift };
         match b.enable(pid) {
             Ok(_) => Ok(b),
             Err(e) => Err(e),
@@ -46,7 +40,6 @@ impl Breakpoint {
     /// Attaches the current breakpoint.
     pub fn enable(&mut self, pid: Pid) -> Result<()> {
         let data = read_address(pid, self.aligned_address())?;
-        self.is_running.insert(pid, true);
         let mut intdata = data & (!(0xFFu64 << self.shift) as i64);
         intdata |= (INT << self.shift) as i64;
         if data == intdata {
@@ -56,7 +49,8 @@ impl Breakpoint {
         }
     }
 
-    fn disable(&self, pid: Pid) -> Result<()> {
+    /// Detaches the current breakpoint, putting back the original instruction.
+    pub fn disable(&self, pid: Pid) -> Result<()> {
         // I require the bit fiddlin this end.
         let data = read_address(pid, self.aligned_address())?;
         let mut orgdata = data & (!(0xFFu64 << self.shift) as i64);
@@ -64,32 +58,29 @@ impl Breakpoint {
         write_to_address(pid, self.aligned_address(), orgdata)
     }
 
-    /// Processes the breakpoint. This steps over the breakpoint
-    pub fn process(&mut self, pid: Pid, reenable: bool) -> Result<bool> {
-        let is_running = match self.is_running.get(&pid) {
-            Some(r) => *r,
-            None => true,
-        };
-        if is_running {
-            let _ = self.enable(pid);
-            self.step(pid)?;
-            self.is_running.insert(pid, false);
-            Ok(true)
-        } else {
-            self.disable(pid)?;
-            if reenable {
-                self.enable(pid)?;
+    /// Steps over the breakpoint and waits for the step to complete so the
+    /// breakpoint can be reenabled straight away. While the original
+    /// instruction is in place any other threads running through it won't be
+    /// seen, so they should be stopped first if every hit matters. Returns the
+    /// status of the tracee after the step.
+    ///
+    /// If a signal stopped the tracee before the step the instruction hasn't
+    /// run, so the breakpoint is always put back. It's then hit again once the
+    /// tracee is resumed after the signal has been handled.
+    pub fn step_over(&mut self, pid: Pid, reenable: bool) -> Result<WaitStatus> {
+        self.step(pid)?;
+        let status = waitpid(pid, Some(WaitPidFlag::__WALL))?;
+        match status {
+            WaitStatus::Stopped(_, Signal::SIGTRAP) | WaitStatus::PtraceEvent(..) => {
+                if reenable {
+                    self.enable(pid)?;
+                }
             }
-            continue_exec(pid, None)?;
-            self.is_running.insert(pid, true);
-            Ok(false)
+            WaitStatus::Stopped(..) => self.enable(pid)?,
+            // The tracee is gone
+            _ => {}
         }
-    }
-
-    /// Call this when a ptrace thread is killed. Won't reenable the breakpoint
-    /// so may lose the ability to instrument this line.
-    pub fn thread_killed(&mut self, pid: Pid) {
-        self.is_running.remove(&pid);
+        Ok(status)
     }
 
     /// Steps past the current breakpoint.
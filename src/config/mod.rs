@@ -1,11 +1,12 @@
 pub use self::types::*;
 
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 use clap::ArgMatches;
 use coveralls_api::CiService;
-use regex::Regex;
+use ignore::gitignore::Gitignore;
 
 use self::parse::*;
 
@@ -33,8 +34,14 @@ pub struct Config {
     pub line_coverage: bool,
     /// Flag specifying to run branch coverage
     pub branch_coverage: bool,
+    /// Flag specifying to run condition coverage
+    pub condition_coverage: bool,
     /// Output files to generate
     pub generate: Vec<OutputFile>,
+    /// Directory to write output files to
+    pub output_directory: PathBuf,
+    /// File names to use for output files instead of the defaults
+    pub report_names: HashMap<OutputFile, String>,
     /// Key relating to coveralls service or repo
     pub coveralls: Option<String>,
     /// Enum representing CI tool used.
@@ -46,6 +53,23 @@ pub struct Config {
     /// Forward unexpected signals back to the tracee. Used for tests which
     /// rely on signals to work.
     pub forward_signals: bool,
+    /// Let the tests run on multiple threads instead of setting
+    /// `RUST_TEST_THREADS=1`
+    pub parallel: bool,
+    /// Trace the doctests as well as the tests
+    pub doc: bool,
+    /// Build and trace the examples in test mode
+    pub examples: bool,
+    /// Build and trace the benchmarks in test mode
+    pub benches: bool,
+    /// Build and trace all binaries in test mode, even those with `test = false`
+    pub bins: bool,
+    /// Run each test separately to record which tests hit each line
+    pub per_test: bool,
+    /// Report coverage of source files generated into OUT_DIR by build scripts
+    pub include_generated: bool,
+    /// Attribute code expanded from `macro_rules!` macros to their definitions
+    pub macro_coverage: bool,
     /// Include all available features in target build
     pub all_features: bool,
     /// Do not include default features in target build
@@ -59,19 +83,42 @@ pub struct Config {
     /// Packages to exclude from testing
     pub exclude: Vec<String>,
     /// Files to exclude from testing
-    excluded_files: Vec<Regex>,
+    excluded_files: Option<Gitignore>,
+    /// Files to include in testing, if given all other files are excluded
+    included_files: Option<Gitignore>,
     /// Varargs to be forwarded to the test executables.
     pub varargs: Vec<String>,
     /// Duration to wait before a timeout occurs
     pub test_timeout: Duration,
     /// Build in release mode
     pub release: bool,
+    /// Fail if the coverage percentage is below this value
+    pub fail_under: Option<f64>,
+    /// File to save the raw coverage data to after a run
+    pub save_raw: Option<PathBuf>,
+    /// File of raw coverage data to generate reports from instead of running
+    /// the tests
+    pub raw_data: Option<PathBuf>,
+    /// Files of raw coverage data to merge into one report
+    pub merge_inputs: Vec<PathBuf>,
+    /// Path prefixes to replace in merged coverage data so files from
+    /// different checkouts line up
+    pub path_prefixes: Vec<(PathBuf, PathBuf)>,
+    /// Raw coverage data of a previous run to compare the results against
+    pub baseline: Option<PathBuf>,
+    /// Unified diff to report the coverage of changed lines from, `-` reads
+    /// it from stdin
+    pub diff: Option<PathBuf>,
+    /// Fail if the coverage percentage of changed lines is below this value
+    pub fail_under_diff: Option<f64>,
 }
 
 impl<'a> From<&'a ArgMatches<'a>> for Config {
     fn from(args: &'a ArgMatches<'a>) -> Self {
+        let manifest = get_manifest(args);
+        let root = manifest.parent().unwrap_or(&manifest).to_path_buf();
         Config {
-            manifest: get_manifest(args),
+            manifest,
             run_ignored: args.is_present("ignored"),
             ignore_tests: args.is_present("ignore-tests"),
             ignore_panics: args.is_present("ignore-panics"),
@@ -80,21 +127,41 @@ impl<'a> From<&'a ArgMatches<'a>> for Config {
             count: args.is_present("count"),
             line_coverage: get_line_cov(args),
             branch_coverage: get_branch_cov(args),
+            condition_coverage: args.is_present("condition"),
             generate: get_outputs(args),
+            output_directory: get_output_directory(args),
+            report_names: get_report_names(args),
             coveralls: get_coveralls(args),
             ci_tool: get_ci(args),
             report_uri: get_report_uri(args),
             forward_signals: args.is_present("forward"),
+            parallel: args.is_present("parallel"),
+            doc: args.is_present("doc"),
+            examples: args.is_present("examples"),
+            benches: args.is_present("benches"),
+            bins: args.is_present("bins"),
+            per_test: args.is_present("per-test"),
+            include_generated: args.is_present("include-generated"),
+            macro_coverage: args.is_present("macro-coverage"),
             all_features: args.is_present("all-features"),
             no_default_features: args.is_present("no-default-features"),
             features: get_list(args, "features"),
             all: args.is_present("all"),
             packages: get_list(args, "packages"),
             exclude: get_list(args, "exclude"),
-            excluded_files: get_excluded(args),
+            excluded_files: get_file_globs(args, "exclude-files", &root),
+            included_files: get_file_globs(args, "include-files", &root),
             varargs: get_list(args, "args"),
             test_timeout: get_timeout(args),
             release: args.is_present("release"),
+            fail_under: get_fail_under(args),
+            save_raw: args.value_of("save-raw").map(PathBuf::from),
+            raw_data: args.value_of("from").map(PathBuf::from),
+            merge_inputs: get_list(args, "input").iter().map(PathBuf::from).collect(),
+            path_prefixes: get_path_prefixes(args),
+            baseline: args.value_of("baseline").map(PathBuf::from),
+            diff: args.value_of("diff").map(PathBuf::from),
+            fail_under_diff: get_fail_under_diff(args),
         }
     }
 }
@@ -105,13 +172,31 @@ impl Config {
         self.coveralls.is_some()
     }
 
-    #[inline]
+    /// Returns true if the file is excluded from the results, either by
+    /// matching an excluded glob or by not matching any included globs.
+    /// Globs follow gitignore rules anchored at the project root
     pub fn exclude_path(&self, path: &Path) -> bool {
         let project = self.strip_project_path(path);
+        // Files outside the project can't be matched
+        let matches = |globs: &Gitignore| {
+            !project.has_root()
+                && globs
+                    .matched_path_or_any_parents(&project, false)
+                    .is_ignore()
+        };
+
+        let included = self.included_files.as_ref().map_or(true, |x| matches(x));
+        let excluded = self.excluded_files.as_ref().map_or(false, |x| matches(x));
+        !included || excluded
+    }
 
-        self.excluded_files
-            .iter()
-            .any(|x| x.is_match(project.to_str().unwrap_or("")))
+    /// Gets the path to write the report of the given format to
+    pub fn report_path(&self, format: OutputFile) -> PathBuf {
+        let name = match self.report_names.get(&format) {
+            Some(name) => name.as_str(),
+            None => format.default_file_name(),
+        };
+        self.output_directory.join(name)
     }
 
     /// Strips the directory the project manifest is in from the path.
@@ -215,6 +300,97 @@ mod tests {
         assert!(!conf.exclude_path(Path::new("lib.rs")));
     }
 
+    #[test]
+    fn exclude_globs() {
+        let matches = App::new("tarpaulin")
+            .args_from_usage(
+                "--exclude-files [FILE]... 'Exclude given files from coverage results'",
+            )
+            .get_matches_from_safe(vec![
+                "tarpaulin",
+                "--exclude-files",
+                "src/**/gen_?.rs",
+                "--exclude-files",
+                "tests/",
+                "--exclude-files",
+                "!tests/keep.rs",
+                "--exclude-files",
+                "[ab].rs",
+            ])
+            .unwrap();
+        let conf = Config::from(&matches);
+        assert!(conf.exclude_path(Path::new("src/gen_1.rs")));
+        assert!(conf.exclude_path(Path::new("src/a/b/gen_x.rs")));
+        assert!(!conf.exclude_path(Path::new("src/gen_10.rs")));
+        assert!(!conf.exclude_path(Path::new("other/src/gen_1.rs")));
+        assert!(conf.exclude_path(Path::new("tests/integration.rs")));
+        assert!(!conf.exclude_path(Path::new("tests/keep.rs")));
+        assert!(!conf.exclude_path(Path::new("src/tests.rs")));
+        assert!(conf.exclude_path(Path::new("src/a.rs")));
+        assert!(!conf.exclude_path(Path::new("src/c.rs")));
+    }
+
+    #[test]
+    fn include_files() {
+        let matches = App::new("tarpaulin")
+            .args_from_usage(
+                "--exclude-files [FILE]... 'Exclude given files from coverage results'
+                 --include-files [FILE]... 'Only include given files in coverage results'",
+            )
+            .get_matches_from_safe(vec![
+                "tarpaulin",
+                "--include-files",
+                "src/",
+                "--exclude-files",
+                "src/generated.rs",
+            ])
+            .unwrap();
+        let conf = Config::from(&matches);
+        assert!(!conf.exclude_path(Path::new("src/lib.rs")));
+        assert!(!conf.exclude_path(Path::new("src/module/file.rs")));
+        assert!(conf.exclude_path(Path::new("src/generated.rs")));
+        assert!(conf.exclude_path(Path::new("tests/lib.rs")));
+        assert!(conf.exclude_path(Path::new("build.rs")));
+    }
+
+    #[test]
+    fn report_paths() {
+        let matches = App::new("tarpaulin")
+            .args_from_usage(
+                "--output-dir [DIR] 'Directory to write reports to'
+                 --report-name [NAME]... 'Report file names'",
+            )
+            .get_matches_from_safe(vec![
+                "tarpaulin",
+                "--output-dir",
+                "coverage",
+                "--report-name",
+                "xml=coverage.xml",
+                "--report-name",
+                "Lcov=coverage.lcov",
+            ])
+            .unwrap();
+        let conf = Config::from(&matches);
+        assert_eq!(
+            conf.report_path(OutputFile::Xml),
+            Path::new("coverage/coverage.xml")
+        );
+        assert_eq!(
+            conf.report_path(OutputFile::Lcov),
+            Path::new("coverage/coverage.lcov")
+        );
+        assert_eq!(
+            conf.report_path(OutputFile::Html),
+            Path::new("coverage/tarpaulin-report.html")
+        );
+
+        let conf = Config::default();
+        assert_eq!(
+            conf.report_path(OutputFile::Xml),
+            Path::new("cobertura.xml")
+        );
+    }
+
     #[test]
     fn relative_path_test() {
         let path_a = Path::new("/this/should/form/a/rel/path/");
@@ -245,4 +421,33 @@ mod tests {
             "Wrong relative path"
         );
     }
+
+    #[test]
+    fn merge_prefixes() {
+        let matches = App::new("tarpaulin")
+            .args_from_usage(
+                "<input>... 'Raw coverage data files'
+                 --replace-prefix [FROM=TO]... 'Path prefixes to replace'",
+            )
+            .get_matches_from_safe(vec![
+                "tarpaulin",
+                "a.json",
+                "b.json",
+                "--replace-prefix",
+                "/ci/build=/home/dev/project",
+            ])
+            .unwrap();
+        let conf = Config::from(&matches);
+        assert_eq!(
+            conf.merge_inputs,
+            vec![PathBuf::from("a.json"), PathBuf::from("b.json")]
+        );
+        assert_eq!(
+            conf.path_prefixes,
+            vec![(
+                PathBuf::from("/ci/build"),
+                PathBuf::from("/home/dev/project")
+            )]
+        );
+    }
 }
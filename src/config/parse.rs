@@ -1,10 +1,11 @@
 use crate::config::types::*;
 use clap::{value_t, values_t, ArgMatches};
 use coveralls_api::CiService;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
 use log::error;
-use regex::Regex;
+use std::collections::HashMap;
 use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 pub(super) fn get_list(args: &ArgMatches, key: &str) -> Vec<String> {
@@ -19,10 +20,7 @@ pub(super) fn get_line_cov(args: &ArgMatches) -> bool {
 }
 
 pub(super) fn get_branch_cov(args: &ArgMatches) -> bool {
-    let cover_lines = args.is_present("line");
-    let cover_branches = args.is_present("branch");
-
-    cover_branches || !(cover_lines || cover_branches)
+    args.is_present("branch")
 }
 
 pub(super) fn get_manifest(args: &ArgMatches) -> PathBuf {
@@ -52,20 +50,74 @@ pub(super) fn get_outputs(args: &ArgMatches) -> Vec<OutputFile> {
     values_t!(args.values_of("out"), OutputFile).unwrap_or(vec![])
 }
 
-pub(super) fn get_excluded(args: &ArgMatches) -> Vec<Regex> {
-    let mut files = vec![];
+pub(super) fn get_output_directory(args: &ArgMatches) -> PathBuf {
+    args.value_of("output-dir")
+        .map(PathBuf::from)
+        .unwrap_or_default()
+}
+
+pub(super) fn get_report_names(args: &ArgMatches) -> HashMap<OutputFile, String> {
+    let mut names = HashMap::new();
+
+    for name in &get_list(args, "report-name") {
+        let mut parts = name.splitn(2, '=');
+        match (parts.next().map(|x| x.parse::<OutputFile>()), parts.next()) {
+            (Some(Ok(format)), Some(file)) if !file.is_empty() => {
+                names.insert(format, file.to_string());
+            }
+            _ => error!("Invalid report name: {}, expected FORMAT=FILE", name),
+        }
+    }
+
+    names
+}
 
-    for temp_str in &get_list(args, "exclude-files") {
-        let s = &temp_str.replace(".", r"\.").replace("*", ".*");
+pub(super) fn get_path_prefixes(args: &ArgMatches) -> Vec<(PathBuf, PathBuf)> {
+    let mut prefixes = vec![];
 
-        if let Ok(re) = Regex::new(s) {
-            files.push(re);
-        } else {
-            error!("Invalid regex: {}", temp_str);
+    for prefix in &get_list(args, "replace-prefix") {
+        let mut parts = prefix.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(from), Some(to)) if !from.is_empty() => {
+                prefixes.push((PathBuf::from(from), PathBuf::from(to)));
+            }
+            _ => error!("Invalid path prefix: {}, expected FROM=TO", prefix),
         }
     }
 
-    files
+    prefixes
+}
+
+/// Builds a matcher from gitignore style globs given for `key`, relative to
+/// the project root. Returns None if no globs were given
+pub(super) fn get_file_globs(args: &ArgMatches, key: &str, root: &Path) -> Option<Gitignore> {
+    let globs = get_list(args, key);
+    if globs.is_empty() {
+        return None;
+    }
+    let mut builder = GitignoreBuilder::new(root);
+
+    for glob in &globs {
+        if let Err(e) = builder.add_line(None, glob) {
+            error!("Invalid glob: {}, {}", glob, e);
+        }
+    }
+
+    match builder.build() {
+        Ok(globs) => Some(globs),
+        Err(e) => {
+            error!("Invalid globs for --{}: {}", key, e);
+            None
+        }
+    }
+}
+
+pub(super) fn get_fail_under(args: &ArgMatches) -> Option<f64> {
+    value_t!(args.value_of("fail-under"), f64).ok()
+}
+
+pub(super) fn get_fail_under_diff(args: &ArgMatches) -> Option<f64> {
+    value_t!(args.value_of("fail-under-diff"), f64).ok()
 }
 
 pub(super) fn get_timeout(args: &ArgMatches) -> Duration {
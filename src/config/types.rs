@@ -4,13 +4,16 @@ use std::str::FromStr;
 use void::Void;
 
 arg_enum! {
-    #[derive(Debug)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
     pub enum OutputFile {
         Json,
         Toml,
         Stdout,
         Xml,
         Html,
+        Lcov,
+        // Written with --baseline rather than picked with --out
+        Delta,
     }
 }
 
@@ -21,6 +24,21 @@ impl Default for OutputFile {
     }
 }
 
+impl OutputFile {
+    /// Name of the file the report is written to unless the user picks one
+    pub fn default_file_name(self) -> &'static str {
+        match self {
+            OutputFile::Json => "tarpaulin-report.json",
+            OutputFile::Toml => "tarpaulin-report.toml",
+            OutputFile::Stdout => "",
+            OutputFile::Xml => "cobertura.xml",
+            OutputFile::Html => "tarpaulin-report.html",
+            OutputFile::Lcov => "lcov.info",
+            OutputFile::Delta => "tarpaulin-delta.json",
+        }
+    }
+}
+
 pub struct Ci(pub CiService);
 
 impl FromStr for Ci {
@@ -24,6 +24,21 @@ pub enum RunError {
     /// Failed to get test coverage
     #[fail(display = "Failed to get test coverage! Error: {}", _0)]
     TestCoverage(String),
+    /// Tests ran but didn't pass
+    #[fail(display = "Test failed! Error: {}", _0)]
+    TestFailed(String),
+    /// Coverage is below the threshold given by the user
+    #[fail(
+        display = "Coverage is below the failure threshold {:.2}% < {:.2}%",
+        _0, _1
+    )]
+    BelowThreshold(f64, f64),
+    /// Coverage of the lines changed in a diff is below the threshold
+    #[fail(
+        display = "Coverage of changed lines is below the failure threshold {:.2}% < {:.2}%",
+        _0, _1
+    )]
+    BelowDiffThreshold(f64, f64),
     #[fail(display = "Failed to trace! Error: {}", _0)]
     Trace(String),
     #[fail(display = "Failed to report coverage! Error: {}", _0)]
@@ -41,6 +56,42 @@ pub enum RunError {
     Html(String),
     #[fail(display = "Failed to generate XML report! Error: {}", _0)]
     XML(quick_xml::Error),
+    #[fail(display = "Failed to generate JSON report! Error: {}", _0)]
+    Json(String),
+    #[fail(display = "Failed to generate TOML report! Error: {}", _0)]
+    Toml(String),
+    #[fail(display = "Failed to save or load raw coverage data! Error: {}", _0)]
+    RawData(String),
+    #[fail(display = "Failed to read diff! Error: {}", _0)]
+    Diff(String),
+    /// The toolchain doesn't support a requested option
+    #[fail(display = "Unsupported toolchain! Error: {}", _0)]
+    Toolchain(String),
+}
+
+impl RunError {
+    /// Gets the process exit code for the error so callers such as CI can tell
+    /// failures apart. Compilation failures give 2, failing tests 3, coverage
+    /// below the threshold 4, failures to report coverage 5 and anything else 1.
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            RunError::Manifest(_)
+            | RunError::Cargo(_)
+            | RunError::Packages(_)
+            | RunError::TestCompile(_)
+            | RunError::Toolchain(_) => 2,
+            RunError::TestFailed(_) => 3,
+            RunError::BelowThreshold(_, _) | RunError::BelowDiffThreshold(_, _) => 4,
+            RunError::CovReport(_)
+            | RunError::OutFormat(_)
+            | RunError::Html(_)
+            | RunError::XML(_)
+            | RunError::Json(_)
+            | RunError::Toml(_)
+            | RunError::RawData(_) => 5,
+            _ => 1,
+        }
+    }
 }
 
 impl From<std::io::Error> for RunError {
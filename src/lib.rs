@@ -9,9 +9,13 @@ use cargo::ops;
 use cargo::util::{homedir, Config as CargoConfig};
 use log::{debug, info, warn};
 use nix::unistd::*;
+use std::collections::HashSet;
 use std::env;
 use std::ffi::CString;
+use std::fs::{create_dir_all, read_dir, remove_dir_all, File};
+use std::io::BufReader;
 use std::path::{Path, PathBuf};
+use std::process::Command;
 
 pub mod breakpoint;
 pub mod config;
@@ -22,14 +26,95 @@ mod statemachine;
 pub mod test_loader;
 pub mod traces;
 
-/// Should be unnecessary with a future nix crate release.
-mod personality;
 mod ptrace_control;
 
 pub fn run(config: &Config) -> Result<(), RunError> {
     let tracemap = launch_tarpaulin(config)?;
-    report_coverage(config, &tracemap)?;
+    if let Some(ref path) = config.save_raw {
+        save_raw_data(path, &tracemap)?;
+    }
+    report_and_check(config, &tracemap)
+}
+
+/// Generates the reports from raw coverage data saved by a previous run
+/// instead of running the tests.
+pub fn run_report(config: &Config) -> Result<(), RunError> {
+    let path = match config.raw_data {
+        Some(ref p) => p,
+        None => return Err(RunError::RawData("No raw data file given".to_string())),
+    };
+    let tracemap = load_raw_data(path)?;
+    report_and_check(config, &tracemap)
+}
+
+/// Merges raw coverage data saved by several runs, for example with different
+/// features or on different machines, and reports the combined results.
+pub fn run_merge(config: &Config) -> Result<(), RunError> {
+    let mut tracemap = TraceMap::new();
+    for path in &config.merge_inputs {
+        let mut data = load_raw_data(path)?;
+        for (from, to) in &config.path_prefixes {
+            data.replace_prefix(from, to);
+        }
+        tracemap.merge(&data);
+    }
+    // Addresses differ between builds so the same line can appear more than once
+    tracemap.dedup();
+    if let Some(ref path) = config.save_raw {
+        save_raw_data(path, &tracemap)?;
+    }
+    report_and_check(config, &tracemap)
+}
 
+/// Saves the coverage results so reports can be generated later without
+/// rerunning the tests.
+pub fn save_raw_data(path: &Path, result: &TraceMap) -> Result<(), RunError> {
+    let file = File::create(path)
+        .map_err(|e| RunError::RawData(format!("Failed to create {}: {}", path.display(), e)))?;
+    serde_json::to_writer(file, result).map_err(|e| RunError::RawData(e.to_string()))?;
+    info!("Raw coverage data saved to {}", path.display());
+    Ok(())
+}
+
+/// Loads coverage results saved with `save_raw_data`.
+pub fn load_raw_data(path: &Path) -> Result<TraceMap, RunError> {
+    let file = File::open(path)
+        .map_err(|e| RunError::RawData(format!("Failed to open {}: {}", path.display(), e)))?;
+    serde_json::from_reader(BufReader::new(file))
+        .map_err(|e| RunError::RawData(format!("Invalid data in {}: {}", path.display(), e)))
+}
+
+/// Reports the coverage results and checks them against the users thresholds
+fn report_and_check(config: &Config, result: &TraceMap) -> Result<(), RunError> {
+    // Read first so nothing is reported if the diff is invalid
+    let changes = match config.diff {
+        Some(ref source) => Some(report::diff::read_diff(source)?),
+        None => None,
+    };
+    report_coverage(config, result)?;
+    let diff_coverage = changes.map(|c| report::diff::DiffCoverage::new(&c, result, config));
+    if let Some(ref diff_coverage) = diff_coverage {
+        diff_coverage.print_summary();
+    }
+    check_fail_threshold(config, result)?;
+    if let Some(threshold) = config.fail_under_diff {
+        if let Some(percent) = diff_coverage.and_then(|x| x.percentage()) {
+            if percent < threshold {
+                return Err(RunError::BelowDiffThreshold(percent, threshold));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns an error if the coverage is below the users threshold
+pub fn check_fail_threshold(config: &Config, result: &TraceMap) -> Result<(), RunError> {
+    if let Some(threshold) = config.fail_under {
+        let percent = result.coverage_percentage() * 100.0f64;
+        if percent < threshold {
+            return Err(RunError::BelowThreshold(percent, threshold));
+        }
+    }
     Ok(())
 }
 
@@ -39,6 +124,9 @@ pub fn launch_tarpaulin(config: &Config) -> Result<TraceMap, RunError> {
         Some(p) => p.to_path_buf(),
         None => PathBuf::new(),
     };
+    if config.macro_coverage {
+        check_nightly("--macro-coverage", &cwd)?;
+    }
     let home = match homedir(&cwd) {
         Some(h) => h,
         None => {
@@ -55,6 +143,7 @@ pub fn launch_tarpaulin(config: &Config) -> Result<TraceMap, RunError> {
     let workspace = Workspace::new(config.manifest.as_path(), &cargo_config)
         .map_err(|e| RunError::Manifest(e.to_string()))?;
 
+    let doctest_dir = workspace.target_dir().join("doctests").into_path_unlocked();
     setup_environment(&config);
 
     let mut copt = ops::CompileOptions::new(&cargo_config, CompileMode::Test)
@@ -79,6 +168,32 @@ pub fn launch_tarpaulin(config: &Config) -> Result<TraceMap, RunError> {
             return Err(RunError::Packages(e.to_string()));
         }
     };
+    if config.examples || config.benches || config.bins {
+        // Everything `cargo test` would build plus the requested targets. The
+        // binaries `cargo test` builds are the ones which don't set `test = false`
+        let bins = if config.bins {
+            vec![]
+        } else {
+            selected_packages(&workspace, &copt.spec)?
+                .iter()
+                .flat_map(|p| p.targets())
+                .filter(|t| t.is_bin() && t.tested())
+                .map(|t| t.name().to_string())
+                .collect()
+        };
+        copt.filter = ops::CompileFilter::new(
+            true,
+            bins,
+            config.bins,
+            vec![],
+            true,
+            vec![],
+            config.examples,
+            vec![],
+            config.benches,
+            false,
+        );
+    }
 
     info!("Running Tarpaulin");
 
@@ -99,21 +214,45 @@ pub fn launch_tarpaulin(config: &Config) -> Result<TraceMap, RunError> {
     let compilation = ops::compile(&workspace, &copt);
     match compilation {
         Ok(comp) => {
+            let binaries = comp
+                .binaries
+                .iter()
+                .map(|b| b.canonicalize().unwrap_or_else(|_| b.clone()))
+                .collect::<HashSet<_>>();
             for &(ref package, ref _target_kind, ref name, ref path) in &comp.tests {
                 debug!("Processing {}", name);
-                if let Some(res) =
-                    get_test_coverage(&workspace, package, path.as_path(), config, false)?
-                {
+                if let Some(res) = get_binary_coverage(
+                    &workspace,
+                    package,
+                    name,
+                    path.as_path(),
+                    &binaries,
+                    config,
+                    false,
+                )? {
                     result.merge(&res);
                 }
                 if config.run_ignored {
-                    if let Some(res) =
-                        get_test_coverage(&workspace, package, path.as_path(), config, true)?
-                    {
+                    if let Some(res) = get_binary_coverage(
+                        &workspace,
+                        package,
+                        name,
+                        path.as_path(),
+                        &binaries,
+                        config,
+                        true,
+                    )? {
                         result.merge(&res);
                     }
                 }
             }
+            if config.doc {
+                if let Some(res) =
+                    get_doctest_coverage(&workspace, copt, &doctest_dir, &binaries, config)?
+                {
+                    result.merge(&res);
+                }
+            }
             result.dedup();
             Ok(result)
         }
@@ -121,20 +260,166 @@ pub fn launch_tarpaulin(config: &Config) -> Result<TraceMap, RunError> {
     }
 }
 
+/// Gets the workspace members selected by the package flags
+fn selected_packages<'a>(
+    project: &'a Workspace,
+    spec: &ops::Packages,
+) -> Result<Vec<&'a Package>, RunError> {
+    let specs = spec
+        .to_package_id_specs(project)
+        .map_err(|e| RunError::Packages(e.to_string()))?;
+    let packages = project
+        .members()
+        .filter(|p| specs.iter().any(|s| s.matches(p.package_id())))
+        .collect();
+    Ok(packages)
+}
+
+/// Builds the doctests of each selected package with rustdoc keeping the
+/// executables in the given directory, then gets the coverage of each executable.
+fn get_doctest_coverage(
+    project: &Workspace,
+    mut copt: ops::CompileOptions,
+    doctest_dir: &Path,
+    binaries: &HashSet<PathBuf>,
+    config: &Config,
+) -> Result<Option<TraceMap>, RunError> {
+    let packages = selected_packages(project, &copt.spec)?;
+    // Don't trace executables of doctests which have since been removed
+    let _ = remove_dir_all(doctest_dir);
+    let rustdocflags = "RUSTDOCFLAGS";
+    let original_flags = env::var_os(rustdocflags);
+    let user_flags = original_flags
+        .as_ref()
+        .map(|f| f.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    copt.build_config.mode = CompileMode::Doctest;
+    copt.filter = ops::CompileFilter::new(
+        true,
+        vec![],
+        false,
+        vec![],
+        false,
+        vec![],
+        false,
+        vec![],
+        false,
+        false,
+    );
+    let mut test_opts = ops::TestOptions {
+        compile_opts: copt,
+        no_run: false,
+        no_fail_fast: true,
+    };
+    let mut trace_packages = || -> Result<Option<TraceMap>, RunError> {
+        let mut result: Option<TraceMap> = None;
+        for package in &packages {
+            let package_dir = doctest_dir.join(package.name().as_str());
+            // Persisting doctests is unstable so needs a nightly rustdoc. The
+            // doctests are run by tarpaulin so rustdoc's runtool does nothing
+            env::set_var(
+                rustdocflags,
+                format!(
+                    " -Z unstable-options --persist-doctests {} --runtool true -C link-dead-code -C opt-level=0 -C debuginfo=2 {}",
+                    package_dir.display(),
+                    user_flags
+                ),
+            );
+            test_opts.compile_opts.spec = ops::Packages::Packages(vec![package.name().to_string()]);
+            info!("Building doctests for {}", package.name());
+            match ops::run_tests(project, &test_opts, &[]) {
+                Ok(None) => {}
+                Ok(Some(e)) => return Err(RunError::TestFailed(e.to_string())),
+                Err(e) => return Err(RunError::TestCompile(e.to_string())),
+            }
+            let mut doctests = match read_dir(&package_dir) {
+                Ok(dir) => dir.filter_map(|e| e.ok()).map(|e| e.path()).collect(),
+                Err(_) => vec![],
+            };
+            doctests.sort();
+            for dir in &doctests {
+                debug!("Processing doctest {}", dir.display());
+                let exe = dir.join("rust_out");
+                if let Some(mut res) =
+                    get_test_coverage(project, package, &exe, binaries, config, false, None)?
+                {
+                    if config.per_test {
+                        let name = dir.file_name().unwrap_or_default().to_string_lossy();
+                        res.add_test_hits(&format!("{}::doctest::{}", package.name(), name));
+                    }
+                    result = match result {
+                        Some(mut r) => {
+                            r.merge(&res);
+                            Some(r)
+                        }
+                        None => Some(res),
+                    };
+                }
+            }
+        }
+        Ok(result)
+    };
+    let result = trace_packages();
+    // Restore the user's flags so later builds aren't affected
+    match original_flags {
+        Some(flags) => env::set_var(rustdocflags, flags),
+        None => env::remove_var(rustdocflags),
+    }
+    result
+}
+
+/// Checks the compiler used to build the project in the given directory accepts
+/// unstable `-Z` flags, which the given option needs
+fn check_nightly(option: &str, dir: &Path) -> Result<(), RunError> {
+    if env::var_os("RUSTC_BOOTSTRAP").is_some() {
+        return Ok(());
+    }
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let output = Command::new(&rustc)
+        .arg("--version")
+        .current_dir(dir)
+        .output()
+        .map_err(|e| RunError::Toolchain(format!("Failed to run {}: {}", rustc, e)))?;
+    let version = String::from_utf8_lossy(&output.stdout);
+    if version.contains("-nightly") || version.contains("-dev") {
+        Ok(())
+    } else {
+        Err(RunError::Toolchain(format!(
+            "{} needs a nightly toolchain but {} is {}",
+            option,
+            rustc,
+            version.trim()
+        )))
+    }
+}
+
 fn setup_environment(config: &Config) {
     let rustflags = "RUSTFLAGS";
-    let mut value =
-        " -C relocation-model=dynamic-no-pic -C link-dead-code -C opt-level=0 -C debuginfo=2 "
-            .to_string();
+    let mut value = " -C link-dead-code -C opt-level=0 -C debuginfo=2 ".to_string();
     if config.release {
         value = format!("{}-C debug-assertions=off ", value);
     }
+    if config.macro_coverage {
+        // Keeps the lines of macro definitions in the debug info instead of
+        // the line the macro is used on
+        value.push_str("-Z debug-macros ");
+    }
     if let Ok(vtemp) = env::var(rustflags) {
         value.push_str(vtemp.as_ref());
     }
     env::set_var(rustflags, value);
 }
 
+/// Formats sorted line numbers as comma separated ranges e.g. `1-3, 7`
+pub(crate) fn format_line_ranges(lines: &[u64]) -> String {
+    let (groups, last_group) = lines
+        .iter()
+        .cloned()
+        .fold((vec![], vec![]), accumulate_lines);
+    let (groups, _) = accumulate_lines((groups, last_group), u64::max_value());
+    groups.join(", ")
+}
+
 fn accumulate_lines(
     (mut acc, mut group): (Vec<String>, Vec<u64>),
     next: u64,
@@ -180,12 +465,25 @@ pub fn report_coverage(config: &Config, result: &TraceMap) -> Result<(), RunErro
                     }
                 }
                 uncovered_lines.sort();
-                let (groups, last_group) = uncovered_lines
-                    .into_iter()
-                    .fold((vec![], vec![]), accumulate_lines);
-                let (groups, _) = accumulate_lines((groups, last_group), u64::max_value());
-                if !groups.is_empty() {
-                    println!("|| {}: {}", path.display(), groups.join(", "));
+                if !uncovered_lines.is_empty() {
+                    println!(
+                        "|| {}: {}",
+                        path.display(),
+                        format_line_ranges(&uncovered_lines)
+                    );
+                }
+            }
+            if result.all_functions().iter().any(|x| x.hits == 0) {
+                println!("|| Uncovered Functions:");
+                for file in result.files() {
+                    let path = config.strip_project_path(file);
+                    for f in result
+                        .functions_in_file(file)
+                        .iter()
+                        .filter(|x| x.hits == 0)
+                    {
+                        println!("|| {}:{}: {}", path.display(), f.start, f.name);
+                    }
                 }
             }
         }
@@ -207,11 +505,36 @@ pub fn report_coverage(config: &Config, result: &TraceMap) -> Result<(), RunErro
             result.total_covered(),
             result.total_coverable()
         );
+        let outcomes = result.total_logic_coverable();
+        if outcomes > 0 {
+            println!(
+                "{}/{} branch and condition outcomes covered",
+                result.total_logic_covered(),
+                outcomes
+            );
+        }
+        let functions = result.all_functions().len();
+        if functions > 0 {
+            println!(
+                "{}/{} functions covered",
+                result.total_functions_covered(),
+                functions
+            );
+        }
         if config.is_coveralls() {
             report::coveralls::export(result, config)?;
             info!("Coverage data sent");
         }
 
+        if !config.generate.is_empty() || config.baseline.is_some() {
+            create_dir_all(&config.output_directory).map_err(|e| {
+                RunError::CovReport(format!(
+                    "Failed to create output directory {}: {}",
+                    config.output_directory.display(),
+                    e
+                ))
+            })?;
+        }
         for g in &config.generate {
             match *g {
                 OutputFile::Xml => {
@@ -220,6 +543,15 @@ pub fn report_coverage(config: &Config, result: &TraceMap) -> Result<(), RunErro
                 OutputFile::Html => {
                     report::html::export(result, config)?;
                 }
+                OutputFile::Json => {
+                    report::json::export(result, config)?;
+                }
+                OutputFile::Toml => {
+                    report::toml::export(result, config)?;
+                }
+                OutputFile::Lcov => {
+                    report::lcov::export(result, config)?;
+                }
                 _ => {
                     return Err(RunError::OutFormat(
                         "Format currently unsupported".to_string(),
@@ -227,6 +559,10 @@ pub fn report_coverage(config: &Config, result: &TraceMap) -> Result<(), RunErro
                 }
             }
         }
+        if let Some(ref path) = config.baseline {
+            let baseline = load_raw_data(path)?;
+            report::delta::export(&baseline, result, config)?;
+        }
 
         Ok(())
     } else {
@@ -236,25 +572,149 @@ pub fn report_coverage(config: &Config, result: &TraceMap) -> Result<(), RunErro
     }
 }
 
-/// Returns the coverage statistics for a test executable in the given workspace
+/// Returns the coverage statistics for a test executable. If the coverage is
+/// attributed to tests each test in the executable is run separately, unless
+/// the executable can't list its tests in which case all the coverage is
+/// attributed to the executable.
+fn get_binary_coverage(
+    project: &Workspace,
+    package: &Package,
+    name: &str,
+    test: &Path,
+    binaries: &HashSet<PathBuf>,
+    config: &Config,
+    ignored: bool,
+) -> Result<Option<TraceMap>, RunError> {
+    if !config.per_test {
+        return get_test_coverage(project, package, test, binaries, config, ignored, None);
+    }
+    if !test.exists() {
+        return Ok(None);
+    }
+    let test_names = list_tests(package, test, config, ignored);
+    // The instrumentation points are the same for every test in the binary
+    let traces = generate_tracemap(project, test, config)?;
+    if test_names.is_empty() {
+        warn!(
+            "Failed to list the tests in {}, running them all at once",
+            test.display()
+        );
+        let res = run_test(
+            project, package, test, traces, binaries, config, ignored, None,
+        )?;
+        return Ok(res.map(|mut res| {
+            res.add_test_hits(name);
+            res
+        }));
+    }
+    let mut result = None;
+    for test_name in test_names {
+        let res = run_test(
+            project,
+            package,
+            test,
+            traces.clone(),
+            binaries,
+            config,
+            ignored,
+            Some(&test_name),
+        )?;
+        if let Some(mut res) = res {
+            res.add_test_hits(&format!("{}::{}", name, test_name));
+            result = match result {
+                Some(mut r) => {
+                    r.merge(&res);
+                    Some(r)
+                }
+                None => Some(res),
+            };
+        }
+    }
+    Ok(result)
+}
+
+/// Lists the tests in a test executable. Executables which don't use the libtest
+/// harness may not support `--list` so failures give an empty list.
+fn list_tests(package: &Package, test: &Path, config: &Config, ignored: bool) -> Vec<String> {
+    let mut cmd = Command::new(test);
+    if let Some(parent) = package.manifest_path().parent() {
+        cmd.current_dir(parent);
+    }
+    cmd.arg("--list");
+    if ignored {
+        cmd.arg("--ignored");
+    }
+    match cmd.args(&config.varargs).output() {
+        Ok(ref output) if output.status.success() => parse_test_list(&output.stdout),
+        Ok(output) => {
+            debug!(
+                "Listing tests in {} failed: {}",
+                test.display(),
+                output.status
+            );
+            vec![]
+        }
+        Err(e) => {
+            debug!("Failed to list tests in {}: {}", test.display(), e);
+            vec![]
+        }
+    }
+}
+
+/// Gets the test names from the output of a test executable run with `--list`
+fn parse_test_list(output: &[u8]) -> Vec<String> {
+    String::from_utf8_lossy(output)
+        .lines()
+        .filter(|l| l.ends_with(": test"))
+        .map(|l| l[..l.len() - ": test".len()].to_string())
+        .collect()
+}
+
+/// Returns the coverage statistics for a test executable in the given workspace.
+/// If a test name is given only that test is run.
 pub fn get_test_coverage(
     project: &Workspace,
     package: &Package,
     test: &Path,
+    binaries: &HashSet<PathBuf>,
     config: &Config,
     ignored: bool,
+    test_name: Option<&str>,
 ) -> Result<Option<TraceMap>, RunError> {
     if !test.exists() {
         return Ok(None);
     }
+    let traces = generate_tracemap(project, test, config)?;
+    run_test(
+        project, package, test, traces, binaries, config, ignored, test_name,
+    )
+}
+
+/// Runs a test executable collecting coverage into the given instrumentation
+/// points. If a test name is given only that test is run. Tests exec'ing one of
+/// the given binaries are followed into it.
+#[allow(clippy::too_many_arguments)]
+fn run_test(
+    project: &Workspace,
+    package: &Package,
+    test: &Path,
+    traces: TraceMap,
+    binaries: &HashSet<PathBuf>,
+    config: &Config,
+    ignored: bool,
+    test_name: Option<&str>,
+) -> Result<Option<TraceMap>, RunError> {
     match fork() {
-        Ok(ForkResult::Parent { child }) => match collect_coverage(project, test, child, config) {
-            Ok(t) => Ok(Some(t)),
-            Err(e) => Err(RunError::TestCoverage(e.to_string())),
-        },
+        Ok(ForkResult::Parent { child }) => {
+            match collect_coverage(project, test, child, traces, binaries, config) {
+                Ok(t) => Ok(Some(t)),
+                Err(e @ RunError::TestFailed(_)) => Err(e),
+                Err(e) => Err(RunError::TestCoverage(e.to_string())),
+            }
+        }
         Ok(ForkResult::Child) => {
             info!("Launching test");
-            execute_test(test, package, ignored, config)?;
+            execute_test(test, package, ignored, test_name, config)?;
             Ok(None)
         }
         Err(err) => Err(RunError::TestCoverage(format!(
@@ -270,40 +730,70 @@ fn collect_coverage(
     project: &Workspace,
     test_path: &Path,
     test: Pid,
+    mut traces: TraceMap,
+    binaries: &HashSet<PathBuf>,
     config: &Config,
 ) -> Result<TraceMap, RunError> {
-    let mut traces = generate_tracemap(project, test_path, config)?;
     {
-        let (mut state, mut data) = create_state_machine(test, &mut traces, config);
+        let (mut state, mut data) =
+            create_state_machine(test, &mut traces, project, binaries, config);
         loop {
             state = state.step(&mut data, config)?;
             if state.is_finished() {
                 if let TestState::End(i) = state {
                     if i != 0 {
-                        return Err(RunError::TestCoverage(
-                            "Test binary exited with non-zero return code".to_string(),
-                        ));
+                        return Err(RunError::TestFailed(format!(
+                            "{} exited with non-zero return code {}",
+                            test_path.display(),
+                            i
+                        )));
                     }
                 }
                 break;
             }
         }
     }
+    traces.resolve_logic();
     Ok(traces)
 }
 
+/// Options of the libtest harness which are followed by a value
+const TEST_OPTIONS_WITH_VALUES: &[&str] = &[
+    "--test-threads",
+    "--logfile",
+    "--color",
+    "--format",
+    "--skip",
+    "-Z",
+];
+
+/// Removes the test name filters from the arguments to a test executable,
+/// keeping the other options and their values. `--exact` is removed as well
+/// as it's added when running a single test
+fn remove_test_filters(args: &[String]) -> Vec<String> {
+    let mut result = vec![];
+    let mut is_value = false;
+    for arg in args {
+        if is_value {
+            result.push(arg.clone());
+            is_value = false;
+        } else if arg.starts_with('-') && arg != "--exact" {
+            is_value = TEST_OPTIONS_WITH_VALUES.contains(&arg.as_str());
+            result.push(arg.clone());
+        }
+    }
+    result
+}
+
 /// Launches the test executable
 fn execute_test(
     test: &Path,
     package: &Package,
     ignored: bool,
+    test_name: Option<&str>,
     config: &Config,
 ) -> Result<(), RunError> {
     let exec_path = CString::new(test.to_str().unwrap()).unwrap();
-    match personality::disable_aslr() {
-        Ok(_) => {}
-        Err(e) => return Err(RunError::TestRuntime(format!("ASLR disable failed: {}", e))),
-    }
     match request_trace() {
         Ok(_) => {}
         Err(e) => return Err(RunError::Trace(e.to_string())),
@@ -313,7 +803,10 @@ fn execute_test(
         let _ = env::set_current_dir(parent);
     }
 
-    let mut envars: Vec<CString> = vec![CString::new("RUST_TEST_THREADS=1").unwrap()];
+    let mut envars: Vec<CString> = Vec::new();
+    if !config.parallel {
+        envars.push(CString::new("RUST_TEST_THREADS=1").unwrap());
+    }
     for (key, value) in env::vars() {
         let mut temp = String::new();
         temp.push_str(key.as_str());
@@ -331,10 +824,53 @@ fn execute_test(
     } else {
         argv.push(CString::new("--quiet").unwrap());
     }
-    for s in &config.varargs {
-        argv.push(CString::new(s.as_bytes()).unwrap_or_default());
+    if let Some(name) = test_name {
+        // The test filters in the arguments were used when listing the tests
+        for s in remove_test_filters(&config.varargs) {
+            argv.push(CString::new(s).unwrap_or_default());
+        }
+        argv.push(CString::new(name).unwrap_or_default());
+        argv.push(CString::new("--exact").unwrap());
+    } else {
+        for s in &config.varargs {
+            argv.push(CString::new(s.as_bytes()).unwrap_or_default());
+        }
     }
     execve(&exec_path, &argv, envars.as_slice()).unwrap();
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filters_removed() {
+        let args = [
+            "--nocapture",
+            "foo",
+            "--test-threads",
+            "2",
+            "--exact",
+            "--skip",
+            "bar",
+            "--color=never",
+            "baz",
+        ]
+        .iter()
+        .map(|x| x.to_string())
+        .collect::<Vec<_>>();
+        assert_eq!(
+            remove_test_filters(&args),
+            vec![
+                "--nocapture",
+                "--test-threads",
+                "2",
+                "--skip",
+                "bar",
+                "--color=never"
+            ]
+        );
+    }
+}
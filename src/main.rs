@@ -1,10 +1,14 @@
 use cargo_tarpaulin::config::*;
-use cargo_tarpaulin::run;
+use cargo_tarpaulin::errors::RunError;
+use cargo_tarpaulin::{run, run_merge, run_report};
 use clap::{crate_version, App, Arg, ArgSettings, SubCommand};
 use env_logger::Builder;
+use ignore::gitignore::GitignoreBuilder;
+use lazy_static::lazy_static;
 use log::error;
 use std::io::Write;
 use std::path::Path;
+use std::process;
 
 fn is_dir(d: String) -> Result<(), String> {
     if Path::new(&d).is_dir() {
@@ -14,6 +18,40 @@ fn is_dir(d: String) -> Result<(), String> {
     }
 }
 
+fn is_percentage(p: String) -> Result<(), String> {
+    match p.parse::<f64>() {
+        Ok(x) if x >= 0.0 && x <= 100.0 => Ok(()),
+        _ => Err(String::from("threshold must be a percentage from 0 to 100")),
+    }
+}
+
+fn is_report_name(name: String) -> Result<(), String> {
+    let mut parts = name.splitn(2, '=');
+    match (parts.next().map(|x| x.parse::<OutputFile>()), parts.next()) {
+        (Some(Ok(_)), Some(file)) if !file.is_empty() => Ok(()),
+        _ => Err(format!(
+            "invalid report name {}, expected FORMAT=FILE with FORMAT one of {}",
+            name,
+            OutputFile::variants().join(", ")
+        )),
+    }
+}
+
+fn is_path_prefix(prefix: String) -> Result<(), String> {
+    let mut parts = prefix.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(from), Some(_)) if !from.is_empty() => Ok(()),
+        _ => Err(format!("invalid path prefix {}, expected FROM=TO", prefix)),
+    }
+}
+
+fn is_glob(glob: String) -> Result<(), String> {
+    match GitignoreBuilder::new("").add_line(None, &glob) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("invalid glob {}: {}", glob, e)),
+    }
+}
+
 fn set_up_logging(verbose: bool) {
     let mut builder = Builder::new();
 
@@ -38,11 +76,46 @@ fn set_up_logging(verbose: bool) {
         .init();
 }
 
+lazy_static! {
+    /// Formats `--out` accepts. Delta reports are written when there's a
+    /// `--baseline` so are only named with `--report-name`
+    static ref OUT_FORMATS: Vec<&'static str> = OutputFile::variants()
+        .iter()
+        .cloned()
+        .filter(|x| *x != "Delta")
+        .collect();
+}
+
 const CI_SERVER_HELP: &'static str = "Name of service, supported services are:
 travis-ci, travis-pro, circle-ci, semaphore, jenkins and codeship.
 If you are interfacing with coveralls.io or another site you can \
 also specify a name that they will recognise. Refer to their documentation for this.";
 
+/// Arguments controlling how the coverage results are reported, shared by a
+/// normal run and the report subcommand
+fn report_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::from_usage("--out -o [FMT]   'Output format of coverage report'")
+            .possible_values(&OUT_FORMATS)
+            .multiple(true)
+            .use_delimiter(true),
+        Arg::from_usage("--fail-under [PERCENTAGE] 'Fail with exit code 4 if the coverage percentage is below this value'")
+            .validator(is_percentage),
+        Arg::from_usage("--diff [FILE] 'Unified diff to report the coverage of added and modified lines from, use - to read it from stdin'"),
+        Arg::from_usage("--fail-under-diff [PERCENTAGE] 'Fail with exit code 4 if the coverage percentage of lines changed in --diff is below this value'")
+            .validator(is_percentage)
+            .requires("diff"),
+        Arg::from_usage("--output-dir [DIR] 'Directory to write output reports to, created if missing'"),
+        Arg::from_usage("--report-name [NAME]... 'File name for a report format as FORMAT=FILE, e.g. Xml=coverage.xml'")
+            .validator(is_report_name),
+        Arg::from_usage("--baseline [FILE] 'Raw coverage data saved with --save-raw to report the change in coverage against'"),
+        Arg::from_usage("--root -r [DIR]  'Root directory containing Cargo.toml to use'")
+            .validator(is_dir),
+        Arg::from_usage("--ciserver [SERVICE] 'CI server being used'")
+            .help(CI_SERVER_HELP),
+    ]
+}
+
 fn main() {
     let args = App::new("cargo-tarpaulin")
         .author("Daniel McKenna, <danielmckenna93@gmail.com>")
@@ -60,8 +133,17 @@ fn main() {
                  --ignored -i 'Run ignored tests as well'
                  --line -l    'Line coverage'
                  --force-clean 'Adds a clean stage to work around cargo bugs that may affect coverage results'
-                 --branch -b  'Branch coverage: NOT IMPLEMENTED'
+                 --branch -b  'Branch coverage of if, while and match expressions'
+                 --condition  'Condition coverage of && and || chains in if and while expressions'
                  --forward -f 'Forwards unexpected signals to test. Tarpaulin will still take signals it is expecting.'
+                 --parallel   'Run tests on multiple threads instead of forcing RUST_TEST_THREADS=1'
+                 --per-test   'Run each test separately to record which tests hit each line'
+                 --doc        'Trace doctests as well, needs a nightly toolchain'
+                 --examples   'Build and trace the tests of examples as well'
+                 --benches    'Build and trace benchmarks as tests as well'
+                 --bins       'Build and trace all binaries including those with test = false'
+                 --include-generated 'Report coverage of code build scripts generate into OUT_DIR'
+                 --macro-coverage 'Report coverage of macro_rules! definitions instead of where the macros are used, needs a nightly toolchain'
                  --coveralls [KEY]  'Coveralls key, either the repo token, or if you're using travis use $TRAVIS_JOB_ID and specify travis-{ci|pro} in --ciserver'
                  --report-uri [URI] 'URI to send report to, only used if the option --coveralls is used'
                  --no-default-features 'Do not include default features'
@@ -70,32 +152,57 @@ fn main() {
                  --all        'Build all packages in the workspace'
                  --packages -p [PACKAGE]... 'Package id specifications for which package should be build. See cargo help pkgid for more info'
                  --exclude -e [PACKAGE]... 'Package id specifications to exclude from coverage. See cargo help pkgid for more info'
-                 --exclude-files [FILE]... 'Exclude given files from coverage results has * wildcard'
                  --timeout -t [SECONDS] 'Integer for the maximum time in seconds without response from test before timeout (default is 1 minute).'
-                 --release   'Build in release mode.'")
+                 --release   'Build in release mode.'
+                 --save-raw [FILE] 'Save the raw coverage data to FILE so reports can be generated later'")
+            .args(&report_args())
+            .args(&[
+                Arg::from_usage("--exclude-files [FILE]... 'Exclude files matching the given gitignore style globs from coverage results'")
+                    .validator(is_glob),
+                Arg::from_usage("--include-files [FILE]... 'Only include files matching the given gitignore style globs in coverage results'")
+                    .validator(is_glob),
+            ])
             .args(&[
-                Arg::from_usage("--out -o [FMT]   'Output format of coverage report'")
-                    .possible_values(&OutputFile::variants())
-                    .multiple(true),
-                Arg::from_usage("--root -r [DIR]  'Root directory containing Cargo.toml to use'")
-                    .validator(is_dir),
-                Arg::from_usage("--ciserver [SERVICE] 'CI server being used'")
-                    .help(CI_SERVER_HELP),
                 Arg::with_name("args")
                     .set(ArgSettings::Last)
                     .multiple(true)
                     .help("Arguments to be passed to the test executables can be used to filter or skip certain tests")
-            ]))
+            ])
+            .subcommand(SubCommand::with_name("report")
+                .about("Generate coverage reports from data saved with --save-raw without running the tests")
+                .args_from_usage(
+                    "--verbose -v 'Show extra output'
+                     --from <FILE> 'Raw coverage data saved with --save-raw'
+                     --coveralls [KEY]  'Coveralls key, either the repo token, or if you're using travis use $TRAVIS_JOB_ID and specify travis-{ci|pro} in --ciserver'
+                     --report-uri [URI] 'URI to send report to, only used if the option --coveralls is used'")
+                .args(&report_args()))
+            .subcommand(SubCommand::with_name("merge")
+                .about("Merge raw coverage data saved with --save-raw from several runs into one report")
+                .args_from_usage(
+                    "--verbose -v 'Show extra output'
+                     <input>... 'Raw coverage data files to merge'
+                     --save-raw [FILE] 'Save the merged raw coverage data to FILE'
+                     --coveralls [KEY]  'Coveralls key, either the repo token, or if you're using travis use $TRAVIS_JOB_ID and specify travis-{ci|pro} in --ciserver'
+                     --report-uri [URI] 'URI to send report to, only used if the option --coveralls is used'")
+                .arg(Arg::from_usage("--replace-prefix [FROM=TO]... 'Replace the path prefix FROM with TO in the inputs so files from different checkouts line up'")
+                    .validator(is_path_prefix))
+                .args(&report_args())))
         .get_matches();
 
     let args = args.subcommand_matches("tarpaulin").unwrap_or(&args);
-    let config = Config::from(args);
+    let (config, runner): (Config, fn(&Config) -> Result<(), RunError>) = match args.subcommand() {
+        ("report", Some(report)) => (Config::from(report), run_report),
+        ("merge", Some(merge)) => (Config::from(merge), run_merge),
+        _ => (Config::from(args), run),
+    };
 
     set_up_logging(config.verbose);
 
+    let result = runner(&config);
     // Since this is the last function we run and don't do any error mitigations (other than
-    // printing the error to the user it's fine to unwrap here
-    run(&config)
-        .map_err(|e| error!("{}", e.to_string()))
-        .unwrap_or_default();
+    // printing the error to the user) exit with a code describing the error
+    if let Err(e) = result {
+        error!("{}", e.to_string());
+        process::exit(e.exit_code());
+    }
 }
@@ -1,5 +1,5 @@
 use nix::errno::Errno;
-use nix::libc::{c_long, c_void};
+use nix::libc::{c_int, c_long, c_void};
 use nix::sys::ptrace::*;
 use nix::sys::signal::Signal;
 use nix::unistd::Pid;
@@ -23,6 +23,19 @@ pub fn detach_child(pid: Pid) -> Result<()> {
     detach(pid)
 }
 
+/// Sends a signal to a single thread within a process
+pub fn signal_thread(pid: Pid, tid: Pid, sig: Signal) -> Result<()> {
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_tgkill,
+            libc::pid_t::from(pid),
+            libc::pid_t::from(tid),
+            sig as c_int,
+        )
+    };
+    Errno::result(ret).map(drop)
+}
+
 pub fn continue_exec(pid: Pid, sig: Option<Signal>) -> Result<()> {
     cont(pid, sig)
 }
@@ -1,6 +1,8 @@
-use crate::config::Config;
+use crate::config::{Config, OutputFile};
 use crate::errors::*;
-use crate::traces::{CoverageStat, TraceMap};
+use crate::traces::{
+    amount_coverable, amount_covered, logic_traces, CoverageStat, Trace, TraceMap,
+};
 use log::info;
 use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, Event};
 use quick_xml::Writer;
@@ -30,6 +32,82 @@ fn write_header<T: Write>(writer: &mut Writer<T>, config: &Config) -> Result<usi
     Ok(writer.write_event(Event::End(BytesEnd::borrowed(b"sources")))?)
 }
 
+/// Fraction of the branch and condition outcomes which have been seen. Code
+/// without branches has none to miss so has a rate of 1
+fn branch_rate(traces: &[&Trace]) -> f64 {
+    let logic = logic_traces(traces);
+    if logic.is_empty() {
+        1.0
+    } else {
+        (amount_covered(&logic) as f64) / (amount_coverable(&logic) as f64)
+    }
+}
+
+/// Marks a line as a branch and adds how many of its outcomes have been seen
+/// if there are branches or conditions on it
+fn push_condition_coverage(line: &mut BytesStart, traces: &[&Trace], number: u64) {
+    let branches = logic_traces(traces)
+        .into_iter()
+        .filter(|x| x.line == number)
+        .collect::<Vec<_>>();
+    if !branches.is_empty() {
+        let covered = amount_covered(&branches);
+        let coverable = amount_coverable(&branches);
+        let condition = format!(
+            "{}% ({}/{})",
+            (covered * 100) / coverable,
+            covered,
+            coverable
+        );
+        line.push_attribute(("branch", "true"));
+        line.push_attribute(("condition-coverage", condition.as_ref()));
+    }
+}
+
+/// Writes the functions in a source file, each method has its entry line as
+/// its only line
+fn write_methods<T: Write>(
+    writer: &mut Writer<T>,
+    filename: &Path,
+    coverage: &TraceMap,
+) -> Result<usize, RunError> {
+    let functions = coverage.functions_in_file(filename);
+    let traces = coverage.get_child_traces(filename);
+    if functions.is_empty() {
+        return Ok(writer.write_event(Event::Empty(BytesStart::borrowed(
+            b"methods",
+            b"methods".len(),
+        )))?);
+    }
+    writer.write_event(Event::Start(BytesStart::borrowed(
+        b"methods",
+        b"methods".len(),
+    )))?;
+    for f in functions {
+        let rate = if f.hits > 0 { "1.0" } else { "0.0" };
+        let body = traces
+            .iter()
+            .filter(|x| x.line >= f.start && x.line <= f.end)
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut method = BytesStart::owned(b"method".to_vec(), b"method".len());
+        method.push_attribute(("name", f.name.as_str()));
+        method.push_attribute(("signature", ""));
+        method.push_attribute(("line-rate", rate));
+        method.push_attribute(("branch-rate", branch_rate(&body).to_string().as_ref()));
+        writer.write_event(Event::Start(method))?;
+        writer.write_event(Event::Start(BytesStart::borrowed(b"lines", b"lines".len())))?;
+        let mut line = BytesStart::owned(b"line".to_vec(), b"line".len());
+        line.push_attribute(("number", f.start.to_string().as_ref()));
+        line.push_attribute(("hits", f.hits.to_string().as_ref()));
+        push_condition_coverage(&mut line, &traces, f.start);
+        writer.write_event(Event::Empty(line))?;
+        writer.write_event(Event::End(BytesEnd::borrowed(b"lines")))?;
+        writer.write_event(Event::End(BytesEnd::borrowed(b"method")))?;
+    }
+    Ok(writer.write_event(Event::End(BytesEnd::borrowed(b"methods")))?)
+}
+
 /// Input only from single source file
 fn write_class<T: Write>(
     writer: &mut Writer<T>,
@@ -51,29 +129,32 @@ fn write_class<T: Write>(
             .to_str()
             .unwrap_or_default();
 
+        let traces = coverage.get_child_traces(filename);
         let mut class = BytesStart::owned(b"class".to_vec(), b"class".len());
         class.push_attribute(("name", name));
         class.push_attribute(("filename", tidy_filename.to_str().unwrap_or_default()));
         class.push_attribute(("line-rate", covered.to_string().as_ref()));
-        class.push_attribute(("branch-rate", "1.0"));
+        class.push_attribute(("branch-rate", branch_rate(&traces).to_string().as_ref()));
         class.push_attribute(("complexity", "0.0"));
         writer.write_event(Event::Start(class))?;
-        writer.write_event(Event::Empty(BytesStart::owned(
-            b"methods".to_vec(),
-            b"methods".len(),
-        )))?;
+        write_methods(writer, filename, coverage)?;
         writer.write_event(Event::Start(BytesStart::borrowed(b"lines", b"lines".len())))?;
-        for trace in coverage.get_child_traces(filename) {
+        for trace in &traces {
             let mut line = BytesStart::owned(b"line".to_vec(), b"line".len());
             line.push_attribute(("number", trace.line.to_string().as_ref()));
             match trace.stats {
                 CoverageStat::Line(hit) => {
                     line.push_attribute(("hits", hit.to_string().as_ref()));
                 }
+                CoverageStat::Branch(_) | CoverageStat::Condition(_) => {
+                    // Branches are written as attributes of the line they're on
+                    continue;
+                }
                 _ => {
                     info!("Coverage statistic currently not implemented for cobertura");
                 }
             }
+            push_condition_coverage(&mut line, &traces, trace.line);
             writer.write_event(Event::Empty(line))?;
         }
         writer.write_event(Event::End(BytesEnd::borrowed(b"lines")))?;
@@ -97,7 +178,8 @@ fn write_package<T: Write>(
     let mut pack = BytesStart::owned(b"package".to_vec(), b"package".len());
     pack.push_attribute(("name", package_name));
     pack.push_attribute(("line-rate", covered.to_string().as_ref()));
-    pack.push_attribute(("branch-rate", "1.0"));
+    let rate = branch_rate(&coverage.get_child_traces(package));
+    pack.push_attribute(("branch-rate", rate.to_string().as_ref()));
     pack.push_attribute(("complexity", "0.0"));
     writer.write_event(Event::Start(pack))?;
     writer.write_event(Event::Start(BytesStart::borrowed(
@@ -116,7 +198,7 @@ fn write_package<T: Write>(
 }
 
 pub fn export(coverage_data: &TraceMap, config: &Config) -> Result<(), RunError> {
-    let mut file = File::create("cobertura.xml").unwrap();
+    let mut file = File::create(config.report_path(OutputFile::Xml))?;
     let mut writer = Writer::new(Cursor::new(Vec::new()));
     writer
         .write_event(Event::Decl(BytesDecl::new(b"1.0", None, None)))
@@ -125,7 +207,8 @@ pub fn export(coverage_data: &TraceMap, config: &Config) -> Result<(), RunError>
     let line_rate = coverage_data.coverage_percentage();
     let mut cov = BytesStart::owned(b"coverage".to_vec(), b"coverage".len());
     cov.push_attribute(("line-rate", line_rate.to_string().as_ref()));
-    cov.push_attribute(("branch-rate", "1.0"));
+    let rate = branch_rate(&coverage_data.all_traces());
+    cov.push_attribute(("branch-rate", rate.to_string().as_ref()));
     cov.push_attribute(("version", "1.9"));
 
     if let Ok(s) = SystemTime::now().duration_since(UNIX_EPOCH) {
@@ -177,3 +260,54 @@ pub fn export(coverage_data: &TraceMap, config: &Config) -> Result<(), RunError>
     let result = writer.into_inner().into_inner();
     Ok(file.write_all(&result)?)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traces::LogicState;
+
+    #[test]
+    fn branch_rates() {
+        let trace = |line, stats| Trace {
+            line,
+            address: Some(line),
+            length: 1,
+            stats,
+        };
+        let lines = vec![trace(1, CoverageStat::Line(1))];
+        let lines = lines.iter().collect::<Vec<_>>();
+        assert!((branch_rate(&lines) - 1.0).abs() < 1e-9);
+
+        let half = LogicState {
+            been_true: true,
+            been_false: false,
+        };
+        let traces = vec![
+            trace(1, CoverageStat::Line(1)),
+            trace(2, CoverageStat::Branch(half)),
+            trace(
+                3,
+                CoverageStat::Condition(vec![half, LogicState::default()]),
+            ),
+        ];
+        let traces = traces.iter().collect::<Vec<_>>();
+        assert!((branch_rate(&traces) - 2.0 / 6.0).abs() < 1e-9);
+
+        let mut line = BytesStart::owned(b"line".to_vec(), b"line".len());
+        push_condition_coverage(&mut line, &traces, 3);
+        let attributes = line
+            .attributes()
+            .map(|x| {
+                let x = x.unwrap();
+                (x.key.to_vec(), x.value.to_vec())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            attributes,
+            vec![
+                (b"branch".to_vec(), b"true".to_vec()),
+                (b"condition-coverage".to_vec(), b"25% (1/4)".to_vec()),
+            ]
+        );
+    }
+}
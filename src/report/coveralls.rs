@@ -30,7 +30,8 @@ pub fn export(coverage_data: &TraceMap, config: &Config) -> Result<(), RunError>
                     }
                 }
             }
-            if let Ok(source) = Source::new(&rel_path, file, &lines, &None, false) {
+            let source_path = coverage_data.source_path(file);
+            if let Ok(source) = Source::new(&rel_path, source_path, &lines, &None, false) {
                 report.add_source(source);
             }
         }
@@ -0,0 +1,238 @@
+use crate::config::{Config, OutputFile};
+use crate::errors::RunError;
+use crate::format_line_ranges;
+use crate::traces::{amount_coverable, amount_covered, line_traces, CoverageStat, Trace, TraceMap};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+
+/// Change in coverage of a single file
+#[derive(Serialize)]
+pub(crate) struct FileDelta {
+    /// Path to the file relative to the project root
+    pub path: String,
+    /// Coverable points hit in the baseline
+    pub previous_covered: usize,
+    /// Coverable points in the baseline
+    pub previous_coverable: usize,
+    /// Coverable points hit now
+    pub covered: usize,
+    /// Coverable points now
+    pub coverable: usize,
+    /// Change in coverage percentage
+    pub change: f64,
+    /// Lines which aren't covered but were in the baseline, or which are new
+    pub newly_uncovered: Vec<u64>,
+    /// Lines not covered in the baseline which are now
+    pub newly_covered: Vec<u64>,
+}
+
+/// Change in coverage between a baseline and the current results
+#[derive(Serialize)]
+pub(crate) struct CoverageDelta {
+    /// Coverage percentage of the baseline
+    pub previous_coverage: f64,
+    /// Coverage percentage now
+    pub coverage: f64,
+    /// Change in coverage percentage
+    pub change: f64,
+    /// Change in every file in either the baseline or the current results
+    pub files: Vec<FileDelta>,
+}
+
+fn percentage(covered: usize, coverable: usize) -> f64 {
+    if coverable == 0 {
+        0.0
+    } else {
+        (covered as f64 / coverable as f64) * 100.0
+    }
+}
+
+/// Gets whether each line with line coverage was hit
+fn covered_lines(traces: &[Trace]) -> BTreeMap<u64, bool> {
+    let mut lines = BTreeMap::new();
+    for t in traces {
+        if let CoverageStat::Line(hits) = t.stats {
+            *lines.entry(t.line).or_insert(false) |= hits > 0;
+        }
+    }
+    lines
+}
+
+impl FileDelta {
+    fn new(path: String, previous: &[Trace], current: &[Trace]) -> Self {
+        let previous_refs = line_traces(&previous.iter().collect::<Vec<_>>());
+        let current_refs = line_traces(&current.iter().collect::<Vec<_>>());
+        let previous_covered = amount_covered(&previous_refs);
+        let previous_coverable = amount_coverable(&previous_refs);
+        let covered = amount_covered(&current_refs);
+        let coverable = amount_coverable(&current_refs);
+
+        let previous_lines = covered_lines(previous);
+        let mut newly_uncovered = vec![];
+        let mut newly_covered = vec![];
+        for (line, &hit) in &covered_lines(current) {
+            match (previous_lines.get(line), hit) {
+                (Some(true), false) | (None, false) => newly_uncovered.push(*line),
+                (Some(false), true) => newly_covered.push(*line),
+                _ => {}
+            }
+        }
+        FileDelta {
+            path,
+            previous_covered,
+            previous_coverable,
+            covered,
+            coverable,
+            change: percentage(covered, coverable)
+                - percentage(previous_covered, previous_coverable),
+            newly_uncovered,
+            newly_covered,
+        }
+    }
+
+    fn is_changed(&self) -> bool {
+        self.covered != self.previous_covered
+            || self.coverable != self.previous_coverable
+            || !self.newly_uncovered.is_empty()
+            || !self.newly_covered.is_empty()
+    }
+}
+
+impl CoverageDelta {
+    pub fn new(baseline: &TraceMap, current: &TraceMap, config: &Config) -> Self {
+        let previous = baseline.iter().collect::<BTreeMap<_, _>>();
+        let now = current.iter().collect::<BTreeMap<_, _>>();
+        let paths = previous.keys().chain(now.keys()).collect::<BTreeSet<_>>();
+        let files = paths
+            .into_iter()
+            .map(|path| {
+                FileDelta::new(
+                    config.strip_project_path(path).display().to_string(),
+                    previous.get(path).map_or(&[][..], |x| x.as_slice()),
+                    now.get(path).map_or(&[][..], |x| x.as_slice()),
+                )
+            })
+            .collect();
+        let previous_coverage = baseline.coverage_percentage() * 100.0;
+        let coverage = current.coverage_percentage() * 100.0;
+        CoverageDelta {
+            previous_coverage,
+            coverage,
+            change: coverage - previous_coverage,
+            files,
+        }
+    }
+
+    fn print_summary(&self) {
+        println!("|| Coverage change from baseline:");
+        for file in self.files.iter().filter(|x| x.is_changed()) {
+            println!(
+                "|| {}: {:.2}% -> {:.2}% ({:+.2}%)",
+                file.path,
+                percentage(file.previous_covered, file.previous_coverable),
+                percentage(file.covered, file.coverable),
+                file.change
+            );
+            if !file.newly_uncovered.is_empty() {
+                println!(
+                    "||   newly uncovered: {}",
+                    format_line_ranges(&file.newly_uncovered)
+                );
+            }
+            if !file.newly_covered.is_empty() {
+                println!(
+                    "||   newly covered: {}",
+                    format_line_ranges(&file.newly_covered)
+                );
+            }
+        }
+        println!(
+            "|| \n{:+.2}% coverage change, {:.2}% -> {:.2}%",
+            self.change, self.previous_coverage, self.coverage
+        );
+    }
+}
+
+/// Prints the change in coverage from the baseline and writes it to a JSON
+/// file for other tools
+pub fn export(baseline: &TraceMap, current: &TraceMap, config: &Config) -> Result<(), RunError> {
+    let delta = CoverageDelta::new(baseline, current, config);
+    delta.print_summary();
+
+    let file = File::create(config.report_path(OutputFile::Delta))
+        .map_err(|e| RunError::CovReport(format!("File is not writeable: {}", e.to_string())))?;
+    serde_json::to_writer_pretty(file, &delta).map_err(|e| RunError::CovReport(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn traces(lines: &[(u64, u64)]) -> Vec<Trace> {
+        lines
+            .iter()
+            .map(|&(line, hits)| Trace {
+                line,
+                address: Some(line),
+                length: 1,
+                stats: CoverageStat::Line(hits),
+            })
+            .collect()
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn percentages() {
+        assert!(close(percentage(0, 0), 0.0));
+        assert!(close(percentage(1, 4), 25.0));
+        assert!(close(percentage(4, 4), 100.0));
+    }
+
+    #[test]
+    fn changed_lines() {
+        let previous = traces(&[(1, 1), (2, 0), (3, 1)]);
+        let current = traces(&[(1, 0), (2, 3), (3, 1), (4, 0), (5, 1)]);
+        let delta = FileDelta::new("src/lib.rs".to_string(), &previous, &current);
+        assert_eq!(delta.previous_covered, 2);
+        assert_eq!(delta.previous_coverable, 3);
+        assert_eq!(delta.covered, 3);
+        assert_eq!(delta.coverable, 5);
+        // Line 4 is new and uncovered so is a regression as well
+        assert_eq!(delta.newly_uncovered, vec![1, 4]);
+        assert_eq!(delta.newly_covered, vec![2]);
+        assert!(delta.is_changed());
+    }
+
+    #[test]
+    fn empty_file() {
+        let delta = FileDelta::new("src/lib.rs".to_string(), &[], &[]);
+        assert!(close(delta.change, 0.0));
+        assert!(delta.newly_uncovered.is_empty());
+        assert!(!delta.is_changed());
+    }
+
+    #[test]
+    fn file_only_in_baseline() {
+        let previous = traces(&[(1, 1), (2, 0)]);
+        let delta = FileDelta::new("src/old.rs".to_string(), &previous, &[]);
+        assert_eq!(delta.previous_coverable, 2);
+        assert_eq!(delta.coverable, 0);
+        assert!(close(delta.change, -50.0));
+        assert!(delta.newly_uncovered.is_empty());
+        assert!(delta.is_changed());
+    }
+
+    #[test]
+    fn file_only_in_current() {
+        let current = traces(&[(1, 1), (2, 0), (3, 0), (4, 2)]);
+        let delta = FileDelta::new("src/new.rs".to_string(), &[], &current);
+        assert!(close(delta.change, 50.0));
+        assert_eq!(delta.newly_uncovered, vec![2, 3]);
+        assert!(delta.newly_covered.is_empty());
+        assert!(delta.is_changed());
+    }
+}
@@ -0,0 +1,303 @@
+use crate::config::Config;
+use crate::errors::RunError;
+use crate::format_line_ranges;
+use crate::traces::{CoverageStat, TraceMap};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Lines added or modified by a diff mapped to the file they're in. Paths are
+/// as given in the diff so are relative to wherever it was generated
+pub type ChangedLines = BTreeMap<PathBuf, BTreeSet<u64>>;
+
+/// Gets the path of the new file from a `+++` header. Returns None if the
+/// file was deleted
+fn parse_path(header: &str) -> Option<PathBuf> {
+    // Some tools append a timestamp after a tab
+    let path = header.split('\t').next().unwrap_or("").trim_end();
+    if path.is_empty() || path == "/dev/null" {
+        None
+    } else if path.starts_with("b/") {
+        Some(PathBuf::from(&path[2..]))
+    } else {
+        Some(PathBuf::from(path))
+    }
+}
+
+/// Parses the start and length of a range in a hunk header e.g. `+12,3`
+fn parse_range(range: &str) -> Option<(u64, u64)> {
+    let mut parts = range[1..].splitn(2, ',');
+    let start = parts.next()?.parse().ok()?;
+    let len = match parts.next() {
+        Some(l) => l.parse().ok()?,
+        None => 1,
+    };
+    Some((start, len))
+}
+
+/// Parses a hunk header `@@ -l,s +l,s @@` returning the lengths of the old
+/// range, and the start and length of the new range
+fn parse_hunk(header: &str) -> Option<(u64, u64, u64)> {
+    let mut parts = header.split_whitespace().skip(1);
+    let old = parts.next().filter(|x| x.starts_with('-'))?;
+    let new = parts.next().filter(|x| x.starts_with('+'))?;
+    let (_, old_len) = parse_range(old)?;
+    let (start, new_len) = parse_range(new)?;
+    Some((old_len, start, new_len))
+}
+
+/// Finds the lines added or modified in each file of a unified diff
+pub fn parse_diff(diff: &str) -> ChangedLines {
+    let mut result = ChangedLines::new();
+    let mut file = None;
+    let mut line = 0;
+    let mut old_left = 0u64;
+    let mut new_left = 0u64;
+    for l in diff.lines() {
+        if old_left > 0 || new_left > 0 {
+            if l.starts_with('+') {
+                if let Some(ref f) = file {
+                    result
+                        .entry(f.clone())
+                        .or_insert_with(BTreeSet::new)
+                        .insert(line);
+                }
+                line += 1;
+                new_left = new_left.saturating_sub(1);
+            } else if l.starts_with('-') {
+                old_left = old_left.saturating_sub(1);
+            } else if !l.starts_with('\\') {
+                // Context line, editors may strip the leading space of blank ones
+                line += 1;
+                old_left = old_left.saturating_sub(1);
+                new_left = new_left.saturating_sub(1);
+            }
+        } else if l.starts_with("+++ ") {
+            file = parse_path(&l[4..]);
+        } else if l.starts_with("@@ ") {
+            if let Some((old_len, start, new_len)) = parse_hunk(l) {
+                line = start;
+                old_left = old_len;
+                new_left = new_len;
+            }
+        }
+    }
+    result
+}
+
+/// Reads a unified diff from a file or stdin if the path is `-`
+pub fn read_diff(source: &Path) -> Result<ChangedLines, RunError> {
+    let diff = if source == Path::new("-") {
+        let mut diff = String::new();
+        io::stdin()
+            .read_to_string(&mut diff)
+            .map(|_| diff)
+            .map_err(|e| RunError::Diff(format!("Failed to read stdin: {}", e)))?
+    } else {
+        fs::read_to_string(source)
+            .map_err(|e| RunError::Diff(format!("Failed to read {}: {}", source.display(), e)))?
+    };
+    Ok(parse_diff(&diff))
+}
+
+/// Finds the root of the repository the project is in, which paths in a diff
+/// are relative to. Falls back to the project directory if there isn't one
+fn repository_root(project: &Path) -> &Path {
+    project
+        .ancestors()
+        .find(|x| x.join(".git").exists())
+        .unwrap_or(project)
+}
+
+/// Makes the paths of the changed files absolute so they can be compared with
+/// the paths in the coverage results
+fn resolve_paths<'a>(
+    changes: &'a ChangedLines,
+    root: &Path,
+) -> BTreeMap<PathBuf, &'a BTreeSet<u64>> {
+    changes
+        .iter()
+        .map(|(k, v)| {
+            let path = root.join(k);
+            (path.canonicalize().unwrap_or(path), v)
+        })
+        .collect()
+}
+
+/// Coverage of the lines changed by a diff
+pub struct DiffCoverage {
+    /// Uncovered changed lines in each file relative to the project root
+    pub uncovered: Vec<(String, Vec<u64>)>,
+    /// Changed lines which were hit
+    pub covered: usize,
+    /// Changed lines which are coverable
+    pub coverable: usize,
+}
+
+impl DiffCoverage {
+    pub fn new(changes: &ChangedLines, result: &TraceMap, config: &Config) -> Self {
+        let mut uncovered = vec![];
+        let mut covered = 0;
+        let mut coverable = 0;
+        let project = config.manifest.parent().unwrap_or(&config.manifest);
+        let changes = resolve_paths(changes, repository_root(project));
+        for (path, traces) in result.iter() {
+            let lines = match changes.get(path) {
+                Some(lines) => lines,
+                None => continue,
+            };
+            let mut hits = BTreeMap::new();
+            for t in traces.iter().filter(|t| lines.contains(&t.line)) {
+                if let CoverageStat::Line(x) = t.stats {
+                    *hits.entry(t.line).or_insert(false) |= x > 0;
+                }
+            }
+            coverable += hits.len();
+            covered += hits.values().filter(|&&x| x).count();
+            let missed = hits
+                .into_iter()
+                .filter(|&(_, hit)| !hit)
+                .map(|(line, _)| line)
+                .collect::<Vec<_>>();
+            if !missed.is_empty() {
+                let name = config.strip_project_path(path).display().to_string();
+                uncovered.push((name, missed));
+            }
+        }
+        DiffCoverage {
+            uncovered,
+            covered,
+            coverable,
+        }
+    }
+
+    /// Coverage percentage of the changed lines, None if no coverable lines
+    /// were changed
+    pub fn percentage(&self) -> Option<f64> {
+        if self.coverable == 0 {
+            None
+        } else {
+            Some((self.covered as f64 / self.coverable as f64) * 100.0)
+        }
+    }
+
+    pub fn print_summary(&self) {
+        if !self.uncovered.is_empty() {
+            println!("|| Uncovered changed lines:");
+            for (path, lines) in &self.uncovered {
+                println!("|| {}: {}", path, format_line_ranges(lines));
+            }
+        }
+        println!(
+            "|| \n{:.2}% diff coverage, {} of {} changed lines covered",
+            self.percentage().unwrap_or(100.0),
+            self.covered,
+            self.coverable
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traces::Trace;
+
+    #[test]
+    fn git_diff() {
+        let diff = "diff --git a/src/lib.rs b/src/lib.rs
+index 1111111..2222222 100644
+--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,4 +1,5 @@
+ fn foo() {
+-    bar();
++    baz();
++    qux();
+ }
+
+@@ -10 +11,2 @@ fn other() {
++// added
+ context
+diff --git a/src/old.rs b/src/old.rs
+deleted file mode 100644
+--- a/src/old.rs
++++ /dev/null
+@@ -1,2 +0,0 @@
+-fn gone() {
+-}
+diff --git a/src/new.rs b/src/new.rs
+new file mode 100644
+--- /dev/null
++++ b/src/new.rs
+@@ -0,0 +1,2 @@
++++x;
++--y;
+";
+        let changes = parse_diff(diff);
+        assert_eq!(changes.len(), 2);
+        assert_eq!(
+            changes[Path::new("src/lib.rs")],
+            vec![2, 3, 11].into_iter().collect::<BTreeSet<_>>()
+        );
+        assert_eq!(
+            changes[Path::new("src/new.rs")],
+            vec![1, 2].into_iter().collect::<BTreeSet<_>>()
+        );
+    }
+
+    #[test]
+    fn plain_diff() {
+        let diff = "--- lib.rs.orig\t2019-01-01 00:00:00
++++ lib.rs\t2019-01-02 00:00:00
+@@ -5,3 +5,3 @@
+ a
+-b
++c
+ d
+";
+        let changes = parse_diff(diff);
+        assert_eq!(
+            changes[Path::new("lib.rs")],
+            vec![6].into_iter().collect::<BTreeSet<_>>()
+        );
+    }
+
+    #[test]
+    fn workspace_paths() {
+        let mut config = Config::default();
+        config.manifest = PathBuf::from("/nonexistent/repo/Cargo.toml");
+        let mut result = TraceMap::new();
+        for member in &["a", "b"] {
+            let file = Path::new("/nonexistent/repo")
+                .join(member)
+                .join("src/lib.rs");
+            for &(line, hits) in &[(1, 1), (2, 0)] {
+                result.add_trace(
+                    &file,
+                    Trace {
+                        line,
+                        address: Some(line),
+                        length: 1,
+                        stats: CoverageStat::Line(hits),
+                    },
+                );
+            }
+        }
+        let mut changes = ChangedLines::new();
+        changes.insert(
+            PathBuf::from("a/src/lib.rs"),
+            vec![1, 2].into_iter().collect(),
+        );
+        // A file with the same name in another member doesn't match
+        changes.insert(PathBuf::from("src/lib.rs"), vec![1].into_iter().collect());
+
+        let coverage = DiffCoverage::new(&changes, &result, &config);
+        assert_eq!(coverage.coverable, 2);
+        assert_eq!(coverage.covered, 1);
+        assert_eq!(
+            coverage.uncovered,
+            vec![("a/src/lib.rs".to_string(), vec![2])]
+        );
+    }
+}
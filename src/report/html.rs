@@ -1,7 +1,8 @@
-use crate::config::Config;
+use crate::config::{Config, OutputFile};
 use crate::errors::*;
 use crate::traces::{Trace, TraceMap};
 use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs::{read_to_string, File};
 use std::io::Write;
 
@@ -10,6 +11,7 @@ struct SourceFile {
     pub path: Vec<String>,
     pub content: String,
     pub traces: Vec<Trace>,
+    pub tests: BTreeMap<u64, BTreeSet<String>>,
     pub covered: usize,
     pub coverable: usize,
 }
@@ -19,10 +21,10 @@ struct CoverageReport {
     pub files: Vec<SourceFile>,
 }
 
-pub fn export(coverage_data: &TraceMap, _config: &Config) -> Result<(), RunError> {
+pub fn export(coverage_data: &TraceMap, config: &Config) -> Result<(), RunError> {
     let mut report = CoverageReport { files: Vec::new() };
     for (path, traces) in coverage_data.iter() {
-        let content = match read_to_string(path) {
+        let content = match read_to_string(coverage_data.source_path(path)) {
             Ok(k) => k,
             Err(e) => {
                 return Err(RunError::Html(format!(
@@ -39,12 +41,16 @@ pub fn export(coverage_data: &TraceMap, _config: &Config) -> Result<(), RunError
                 .collect(),
             content,
             traces: traces.clone(),
+            tests: coverage_data
+                .tests_in_file(path)
+                .cloned()
+                .unwrap_or_default(),
             covered: coverage_data.covered_in_path(path),
             coverable: coverage_data.coverable_in_path(path),
         });
     }
 
-    let mut file = match File::create("tarpaulin-report.html") {
+    let mut file = match File::create(config.report_path(OutputFile::Html)) {
         Ok(k) => k,
         Err(e) => {
             return Err(RunError::Html(format!(
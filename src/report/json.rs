@@ -0,0 +1,191 @@
+use crate::config::{Config, OutputFile};
+use crate::errors::RunError;
+use crate::traces::{Function, Trace, TraceMap};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Version of the report layout. This is incremented whenever a change is made
+/// which could break consumers of the report.
+pub const REPORT_VERSION: u32 = 1;
+
+/// Information about the tarpaulin run which produced the report
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Metadata {
+    /// Version of tarpaulin used to generate the report
+    pub tarpaulin_version: String,
+    /// Features the project was built with
+    pub features: Vec<String>,
+    /// Whether the project was built with all features
+    pub all_features: bool,
+    /// Whether the project was built without default features
+    pub no_default_features: bool,
+    /// Time the report was generated in seconds since the unix epoch
+    pub timestamp: u64,
+}
+
+/// Coverage of a single source file
+#[derive(Serialize, Deserialize)]
+pub(crate) struct FileReport {
+    /// Path to the file relative to the project root
+    pub path: String,
+    /// Amount of coverable points hit in the file
+    pub covered: usize,
+    /// Amount of coverable points in the file
+    pub coverable: usize,
+    /// Coverage statistics for each line in the file
+    pub traces: Vec<Trace>,
+    /// Names of the tests which hit each line, only present with `--per-test`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tests: BTreeMap<u64, BTreeSet<String>>,
+    /// Functions in the file and how often each was entered
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub functions: Vec<Function>,
+}
+
+/// Report layout shared by the JSON and TOML reports
+#[derive(Serialize, Deserialize)]
+pub(crate) struct CoverageReport {
+    /// Version of the report layout, see `REPORT_VERSION`
+    pub version: u32,
+    /// Amount of coverable points hit in the project
+    pub covered: usize,
+    /// Amount of coverable points in the project
+    pub coverable: usize,
+    /// Coverage percentage from 0 to 100
+    pub coverage: f64,
+    /// Amount of functions entered at least once
+    pub functions_covered: usize,
+    /// Amount of functions in the project
+    pub functions_coverable: usize,
+    /// Information about the run
+    pub metadata: Metadata,
+    /// Coverage of each file
+    pub files: Vec<FileReport>,
+}
+
+impl CoverageReport {
+    pub fn new(coverage_data: &TraceMap, config: &Config) -> CoverageReport {
+        let timestamp = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(s) => s.as_secs(),
+            Err(_) => 0,
+        };
+        let files = coverage_data
+            .iter()
+            .map(|(path, traces)| FileReport {
+                path: config.strip_project_path(path).display().to_string(),
+                covered: coverage_data.covered_in_path(path),
+                coverable: coverage_data.coverable_in_path(path),
+                traces: traces.clone(),
+                tests: coverage_data
+                    .tests_in_file(path)
+                    .cloned()
+                    .unwrap_or_default(),
+                functions: coverage_data.functions_in_file(path).to_vec(),
+            })
+            .collect();
+        let coverable = coverage_data.total_coverable();
+        let coverage = if coverable > 0 {
+            coverage_data.coverage_percentage() * 100.0f64
+        } else {
+            0.0f64
+        };
+        CoverageReport {
+            version: REPORT_VERSION,
+            covered: coverage_data.total_covered(),
+            coverable,
+            coverage,
+            functions_covered: coverage_data.total_functions_covered(),
+            functions_coverable: coverage_data.all_functions().len(),
+            metadata: Metadata {
+                tarpaulin_version: env!("CARGO_PKG_VERSION").to_string(),
+                features: config.features.clone(),
+                all_features: config.all_features,
+                no_default_features: config.no_default_features,
+                timestamp,
+            },
+            files,
+        }
+    }
+}
+
+pub fn export(coverage_data: &TraceMap, config: &Config) -> Result<(), RunError> {
+    let report = CoverageReport::new(coverage_data, config);
+    let file = match File::create(config.report_path(OutputFile::Json)) {
+        Ok(k) => k,
+        Err(e) => {
+            return Err(RunError::Json(format!(
+                "File is not writeable: {}",
+                e.to_string()
+            )))
+        }
+    };
+    match serde_json::to_writer_pretty(file, &report) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(RunError::Json(e.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traces::CoverageStat;
+    use std::path::PathBuf;
+
+    fn test_config() -> Config {
+        let mut config = Config::default();
+        config.manifest = PathBuf::from("/project/Cargo.toml");
+        config.features = vec!["extra".to_string()];
+        config
+    }
+
+    fn test_traces() -> TraceMap {
+        let mut traces = TraceMap::new();
+        for &(line, hits) in &[(1, 2), (2, 0), (3, 1), (4, 0)] {
+            traces.add_trace(
+                &PathBuf::from("/project/src/lib.rs"),
+                Trace {
+                    line,
+                    address: Some(line),
+                    length: 1,
+                    stats: CoverageStat::Line(hits),
+                },
+            );
+        }
+        traces
+    }
+
+    #[test]
+    fn report_from_traces() {
+        let config = test_config();
+        let report = CoverageReport::new(&test_traces(), &config);
+        assert_eq!(report.version, REPORT_VERSION);
+        assert_eq!(report.covered, 2);
+        assert_eq!(report.coverable, 4);
+        assert!((report.coverage - 50.0).abs() < 1e-9);
+        assert_eq!(report.metadata.features, vec!["extra"]);
+        assert_eq!(report.files.len(), 1);
+        assert_eq!(report.files[0].path, "src/lib.rs");
+        assert_eq!(report.files[0].traces.len(), 4);
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["files"][0]["traces"][0]["stats"]["Line"], 2);
+        // Per-test results and functions are left out when there are none
+        assert!(json["files"][0].get("tests").is_none());
+        assert!(json["files"][0].get("functions").is_none());
+    }
+
+    #[test]
+    fn json_roundtrip() {
+        let config = test_config();
+        let report = CoverageReport::new(&test_traces(), &config);
+        let json = serde_json::to_string_pretty(&report).unwrap();
+        let parsed: CoverageReport = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            serde_json::to_value(&parsed).unwrap(),
+            serde_json::to_value(&report).unwrap()
+        );
+        assert_eq!(parsed.files[0].traces, report.files[0].traces);
+    }
+}
@@ -0,0 +1,174 @@
+use crate::config::{Config, OutputFile};
+use crate::errors::RunError;
+use crate::traces::{CoverageStat, LogicState, TraceMap};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/// Formats the taken field of a BRDA record. LCOV expects a `-` when the
+/// decision was never evaluated
+fn taken(executed: bool, taken: bool) -> &'static str {
+    match (executed, taken) {
+        (false, _) => "-",
+        (true, true) => "1",
+        (true, false) => "0",
+    }
+}
+
+fn write_branch<T: Write>(
+    writer: &mut T,
+    line: u64,
+    block: usize,
+    state: &LogicState,
+    executed: bool,
+) -> Result<(), RunError> {
+    writeln!(
+        writer,
+        "BRDA:{},{},0,{}",
+        line,
+        block,
+        taken(executed, state.been_true)
+    )?;
+    writeln!(
+        writer,
+        "BRDA:{},{},1,{}",
+        line,
+        block,
+        taken(executed, state.been_false)
+    )?;
+    Ok(())
+}
+
+pub fn export(coverage_data: &TraceMap, config: &Config) -> Result<(), RunError> {
+    let file = File::create(config.report_path(OutputFile::Lcov))?;
+    let mut writer = BufWriter::new(file);
+    write_tracefile(&mut writer, coverage_data)?;
+    Ok(writer.flush()?)
+}
+
+fn write_tracefile<T: Write>(writer: &mut T, coverage_data: &TraceMap) -> Result<(), RunError> {
+    writeln!(writer, "TN:")?;
+    for (path, traces) in coverage_data.iter() {
+        writeln!(writer, "SF:{}", path.display())?;
+
+        let functions = coverage_data.functions_in_file(path);
+        for f in functions {
+            writeln!(writer, "FN:{},{}", f.start, f.name)?;
+        }
+        for f in functions {
+            writeln!(writer, "FNDA:{},{}", f.hits, f.name)?;
+        }
+        if !functions.is_empty() {
+            writeln!(writer, "FNF:{}", functions.len())?;
+            writeln!(
+                writer,
+                "FNH:{}",
+                functions.iter().filter(|x| x.hits > 0).count()
+            )?;
+        }
+
+        let mut block = 0;
+        let mut branches_found = 0;
+        let mut branches_hit = 0;
+        for trace in traces {
+            let states = match trace.stats {
+                CoverageStat::Branch(ref s) => vec![*s],
+                CoverageStat::Condition(ref s) => s.clone(),
+                CoverageStat::Line(_) => continue,
+            };
+            let executed = states.iter().any(|s| s.been_true || s.been_false);
+            for s in &states {
+                write_branch(&mut *writer, trace.line, block, s, executed)?;
+                branches_found += 2;
+                branches_hit += s.been_true as usize + s.been_false as usize;
+                block += 1;
+            }
+        }
+        if branches_found > 0 {
+            writeln!(writer, "BRF:{}", branches_found)?;
+            writeln!(writer, "BRH:{}", branches_hit)?;
+        }
+
+        let mut lines_found = 0;
+        let mut lines_hit = 0;
+        for trace in traces {
+            if let CoverageStat::Line(hits) = trace.stats {
+                writeln!(writer, "DA:{},{}", trace.line, hits)?;
+                lines_found += 1;
+                if hits > 0 {
+                    lines_hit += 1;
+                }
+            }
+        }
+        writeln!(writer, "LF:{}", lines_found)?;
+        writeln!(writer, "LH:{}", lines_hit)?;
+        writeln!(writer, "end_of_record")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traces::{Function, Trace};
+    use std::path::Path;
+
+    #[test]
+    fn tracefile_records() {
+        let file = Path::new("/project/src/lib.rs");
+        let mut traces = TraceMap::new();
+        let trace = |line, stats| Trace {
+            line,
+            address: None,
+            length: 0,
+            stats,
+        };
+        traces.add_trace(file, trace(1, CoverageStat::Line(2)));
+        traces.add_trace(file, trace(2, CoverageStat::Line(0)));
+        traces.add_trace(
+            file,
+            trace(
+                1,
+                CoverageStat::Branch(LogicState {
+                    been_true: true,
+                    been_false: false,
+                }),
+            ),
+        );
+        traces.add_trace(
+            file,
+            trace(3, CoverageStat::Condition(vec![LogicState::default()])),
+        );
+        traces.add_function(
+            file,
+            Function {
+                name: "project::foo".to_string(),
+                start: 1,
+                end: 2,
+                hits: 2,
+                addresses: vec![],
+            },
+        );
+        let mut output = vec![];
+        write_tracefile(&mut output, &traces).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let expected = "TN:
+SF:/project/src/lib.rs
+FN:1,project::foo
+FNDA:2,project::foo
+FNF:1
+FNH:1
+BRDA:1,0,0,1
+BRDA:1,0,1,0
+BRDA:3,1,0,-
+BRDA:3,1,1,-
+BRF:4
+BRH:1
+DA:1,2
+DA:2,0
+LF:2
+LH:1
+end_of_record
+";
+        assert_eq!(output, expected);
+    }
+}
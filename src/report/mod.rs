@@ -4,7 +4,12 @@ use serde::Serialize;
 
 pub mod cobertura;
 pub mod coveralls;
+pub mod delta;
+pub mod diff;
 pub mod html;
+pub mod json;
+pub mod lcov;
+pub mod toml;
 /// Trait for report formats to implement.
 /// Currently reports must be serializable using serde
 pub trait Report<Out: Serialize> {
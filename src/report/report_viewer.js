@@ -189,14 +189,19 @@ function FileHeader({file, onBack}) {
 function FileContent({file}) {
   return e('div', {className: 'file-content'},
     file.content.split(/\r?\n/).map((line, index) => {
-      const trace = file.traces.find(trace => trace.line === index + 1);
+      const trace = file.traces.find(trace => trace.line === index + 1 && 'Line' in trace.stats);
       const covered = trace && trace.stats.Line;
       const uncovered = trace && !trace.stats.Line;
+      const tests = file.tests[index + 1];
+      let title = trace ? JSON.stringify(trace.stats, null, 2) : null;
+      if (title && tests) {
+        title += '\nHit by:\n' + tests.join('\n');
+      }
       return e('pre', {
           className: 'code-line'
             + (covered ? ' code-line_covered' : '')
             + (uncovered ? ' code-line_uncovered' : ''),
-          title: trace ? JSON.stringify(trace.stats, null, 2) : null,
+          title,
         }, line);
     })
   );
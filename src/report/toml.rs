@@ -0,0 +1,131 @@
+use crate::config::{Config, OutputFile};
+use crate::errors::RunError;
+use crate::report::json::CoverageReport;
+use crate::traces::TraceMap;
+use serde_json::Value as Json;
+use std::fs::File;
+use std::io::Write;
+use toml::Value as Toml;
+
+/// TOML has no null and can't represent enums like `CoverageStat` directly so
+/// convert the JSON form of the report, dropping any null values.
+fn json_to_toml(value: Json) -> Option<Toml> {
+    match value {
+        Json::Null => None,
+        Json::Bool(b) => Some(Toml::Boolean(b)),
+        Json::Number(n) => n
+            .as_i64()
+            .map(Toml::Integer)
+            .or_else(|| n.as_f64().map(Toml::Float)),
+        Json::String(s) => Some(Toml::String(s)),
+        Json::Array(a) => Some(Toml::Array(
+            a.into_iter().filter_map(json_to_toml).collect(),
+        )),
+        Json::Object(o) => Some(Toml::Table(
+            o.into_iter()
+                .filter_map(|(k, v)| json_to_toml(v).map(|v| (k, v)))
+                .collect(),
+        )),
+    }
+}
+
+/// Converts the same report as the JSON output to TOML.
+fn report_to_toml(coverage_data: &TraceMap, config: &Config) -> Result<String, RunError> {
+    let report = CoverageReport::new(coverage_data, config);
+    let report = match serde_json::to_value(&report) {
+        Ok(k) => json_to_toml(k).unwrap_or_else(|| Toml::Table(Default::default())),
+        Err(e) => {
+            return Err(RunError::Toml(format!(
+                "Report isn't serializable: {}",
+                e.to_string()
+            )))
+        }
+    };
+    match toml::to_string_pretty(&report) {
+        Ok(k) => Ok(k),
+        Err(e) => Err(RunError::Toml(format!(
+            "Report isn't serializable: {}",
+            e.to_string()
+        ))),
+    }
+}
+
+/// Exports the same report as the JSON output in TOML.
+pub fn export(coverage_data: &TraceMap, config: &Config) -> Result<(), RunError> {
+    let report = report_to_toml(coverage_data, config)?;
+    let mut file = match File::create(config.report_path(OutputFile::Toml)) {
+        Ok(k) => k,
+        Err(e) => {
+            return Err(RunError::Toml(format!(
+                "File is not writeable: {}",
+                e.to_string()
+            )))
+        }
+    };
+    match file.write_all(report.as_bytes()) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(RunError::Toml(e.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::json::REPORT_VERSION;
+    use crate::traces::{CoverageStat, Trace};
+    use std::path::PathBuf;
+
+    fn report() -> String {
+        let mut config = Config::default();
+        config.manifest = PathBuf::from("/project/Cargo.toml");
+        let mut traces = TraceMap::new();
+        for &(line, hits) in &[(1, 3), (2, 0)] {
+            traces.add_trace(
+                &PathBuf::from("/project/src/lib.rs"),
+                Trace {
+                    line,
+                    address: None,
+                    length: 1,
+                    stats: CoverageStat::Line(hits),
+                },
+            );
+        }
+        report_to_toml(&traces, &config).unwrap()
+    }
+
+    #[test]
+    fn report_from_traces() {
+        let report = report().parse::<Toml>().unwrap();
+        assert_eq!(
+            report["version"].as_integer(),
+            Some(i64::from(REPORT_VERSION))
+        );
+        assert_eq!(report["covered"].as_integer(), Some(1));
+        assert_eq!(report["coverable"].as_integer(), Some(2));
+        let file = &report["files"][0];
+        assert_eq!(file["path"].as_str(), Some("src/lib.rs"));
+        // Null addresses can't be written in TOML so are left out
+        assert!(file["traces"][0].get("address").is_none());
+        assert_eq!(file["traces"][0]["stats"]["Line"].as_integer(), Some(3));
+    }
+
+    #[test]
+    fn toml_roundtrip() {
+        let report = report().parse::<Toml>().unwrap();
+        let report: CoverageReport =
+            serde_json::from_value(serde_json::to_value(report).unwrap()).unwrap();
+        assert_eq!(report.version, REPORT_VERSION);
+        assert_eq!(report.covered, 1);
+        assert_eq!(report.coverable, 2);
+        assert_eq!(report.files[0].path, "src/lib.rs");
+        assert_eq!(
+            report.files[0].traces[0],
+            Trace {
+                line: 1,
+                address: None,
+                length: 1,
+                stats: CoverageStat::Line(3),
+            }
+        );
+    }
+}
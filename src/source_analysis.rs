@@ -1,7 +1,8 @@
 use crate::config::Config;
+use crate::traces::{BranchLines, ConditionLines, Operator};
 use cargo::core::Workspace;
 use lazy_static::lazy_static;
-use proc_macro2::{Span, TokenStream, TokenTree};
+use proc_macro2::{Delimiter, Span, TokenStream, TokenTree};
 use regex::Regex;
 use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
@@ -9,6 +10,7 @@ use std::ffi::OsStr;
 use std::fs::File;
 use std::io::{BufRead, BufReader, Read};
 use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 use syn::{
     punctuated::Punctuated,
     punctuated::{Pair, Pair::End},
@@ -35,6 +37,14 @@ pub struct LineAnalysis {
     /// But may be ignored. Doesn't make sense to cover ALL the lines so this
     /// is just an index.
     pub cover: HashSet<usize>,
+    /// Decision points found in the source and the lines used to determine
+    /// whether their branches were taken
+    pub branches: HashSet<BranchLines>,
+    /// Chains of short-circuiting conditions guarding branches
+    pub conditions: HashSet<ConditionLines>,
+    /// Continuation lines of multi-line expressions mapped to the line the
+    /// expression starts on, hits on them are counted on that line
+    pub merged: HashMap<usize, usize>,
 }
 
 /// When the LineAnalysis results are mapped to their files there needs to be
@@ -42,6 +52,7 @@ pub struct LineAnalysis {
 /// this trait
 pub trait SourceAnalysisQuery {
     fn should_ignore(&self, path: &Path, l: &usize) -> bool;
+    fn merged_line(&self, path: &Path, l: usize) -> usize;
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -58,6 +69,13 @@ impl SourceAnalysisQuery for HashMap<PathBuf, LineAnalysis> {
             false
         }
     }
+
+    fn merged_line(&self, path: &Path, l: usize) -> usize {
+        match self.get(path) {
+            Some(analysis) => analysis.merged_line(l),
+            None => l,
+        }
+    }
 }
 
 impl LineAnalysis {
@@ -66,12 +84,18 @@ impl LineAnalysis {
         LineAnalysis {
             ignore: HashSet::new(),
             cover: HashSet::new(),
+            branches: HashSet::new(),
+            conditions: HashSet::new(),
+            merged: HashMap::new(),
         }
     }
 
     pub fn ignore_all(&mut self) {
         self.ignore.clear();
         self.cover.clear();
+        self.branches.clear();
+        self.conditions.clear();
+        self.merged.clear();
         self.ignore.insert(Lines::All);
     }
 
@@ -132,6 +156,52 @@ impl LineAnalysis {
         self.ignore.contains(&Lines::Line(line)) || self.ignore.contains(&Lines::All)
     }
 
+    /// Gets the line hits on the given line are counted on, for continuation
+    /// lines of a multi-line expression this is the line it starts on
+    pub fn merged_line(&self, line: usize) -> usize {
+        let mut line = line;
+        // Heads are always before their continuation lines so this ends
+        while let Some(head) = self.merged.get(&line) {
+            line = *head;
+        }
+        line
+    }
+
+    /// Adds a branch to the analysis. If the branch body starts on the same
+    /// line as the decision the outcomes can't be told apart so it's skipped.
+    fn add_branch(&mut self, line: usize, decision: usize, taken: usize) {
+        if decision != taken {
+            self.branches.insert(BranchLines {
+                line: line as u64,
+                decision: decision as u64,
+                taken: taken as u64,
+            });
+        }
+    }
+
+    /// Adds the conditions of a decision if it's a chain of `&&` or `||`.
+    /// Like branches the body has to start on a different line to the decision
+    fn add_conditions(&mut self, line: usize, taken: usize, cond: &Expr) {
+        if line == taken {
+            return;
+        }
+        if let Some((operator, conditions)) = get_conditions(cond) {
+            let conditions = conditions
+                .iter()
+                .map(|c| {
+                    let start = c.span().start();
+                    (start.line as u64, start.column as u64 + 1)
+                })
+                .collect();
+            self.conditions.insert(ConditionLines {
+                line: line as u64,
+                taken: taken as u64,
+                operator,
+                conditions,
+            });
+        }
+    }
+
     /// Adds a line to the list of lines to ignore
     fn add_to_ignore(&mut self, lines: &[usize]) {
         if !self.ignore.contains(&Lines::All) {
@@ -143,6 +213,17 @@ impl LineAnalysis {
             }
         }
     }
+
+    /// Merges the continuation lines of an expression into the line it starts
+    /// on so they aren't reported separately
+    fn merge_lines(&mut self, lines: &[usize], head: usize) {
+        if !self.ignore.contains(&Lines::All) {
+            for l in lines.iter().filter(|l| **l > head) {
+                self.merged.entry(*l).or_insert(head);
+            }
+        }
+        self.add_to_ignore(lines);
+    }
 }
 
 fn is_source_file(entry: &DirEntry) -> bool {
@@ -155,8 +236,103 @@ fn is_target_folder(entry: &DirEntry, root: &Path) -> bool {
     entry.path().starts_with(&target)
 }
 
-/// Returns a list of files and line numbers to ignore (not indexes!)
-pub fn get_line_analysis(project: &Workspace, config: &Config) -> HashMap<PathBuf, LineAnalysis> {
+/// Gets the package a build script output directory belongs to from its path
+/// relative to the target directory. Build script output goes in
+/// `build/<package>-<hash>/out`
+fn out_dir_package(path: &Path) -> Option<String> {
+    let components = path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>();
+    let n = components.len();
+    if n >= 3 && components[n - 3] == "build" && components[n - 1] == "out" {
+        components[n - 2]
+            .rsplitn(2, '-')
+            .nth(1)
+            .map(|x| x.to_string())
+    } else {
+        None
+    }
+}
+
+/// Finds the source files the build scripts of workspace members generated
+/// into their OUT_DIR. These are returned mapped to the path they're reported
+/// under, `OUT_DIR/<file>` in the directory of the package that generated them.
+/// Out dirs of old builds are left in the target directory, so only the one
+/// whose build script ran most recently is used for each package
+pub fn find_generated_sources(project: &Workspace) -> HashMap<PathBuf, PathBuf> {
+    let target = project.target_dir().into_path_unlocked();
+    let target = target.canonicalize().unwrap_or(target);
+    let packages = project
+        .members()
+        .map(|p| (p.name().to_string(), p.root().to_path_buf()))
+        .collect::<HashMap<_, _>>();
+
+    let mut out_dirs: HashMap<String, (SystemTime, PathBuf)> = HashMap::new();
+    let walker = WalkDir::new(&target).into_iter();
+    for e in walker
+        .filter_entry(|e| {
+            let name = e.file_name().to_str().unwrap_or_default();
+            !["deps", "incremental", ".fingerprint", "doc"].contains(&name)
+        })
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_dir())
+    {
+        let path = e
+            .path()
+            .canonicalize()
+            .unwrap_or_else(|_| e.path().to_path_buf());
+        let package = match path.strip_prefix(&target).ok().and_then(out_dir_package) {
+            Some(p) => p,
+            None => continue,
+        };
+        if !packages.contains_key(&package) {
+            continue;
+        }
+        // Cargo writes the build script's output next to the out dir each
+        // time the script runs
+        let modified = path
+            .with_file_name("output")
+            .metadata()
+            .or_else(|_| path.metadata())
+            .and_then(|m| m.modified())
+            .unwrap_or(UNIX_EPOCH);
+        let newest = out_dirs
+            .get(&package)
+            .map_or(true, |&(time, _)| modified > time);
+        if newest {
+            out_dirs.insert(package, (modified, path));
+        }
+    }
+
+    let mut result = HashMap::new();
+    for (package, (_, out)) in out_dirs {
+        let root = packages[&package].join("OUT_DIR");
+        for e in WalkDir::new(&out)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| is_source_file(e))
+        {
+            let path = e
+                .path()
+                .canonicalize()
+                .unwrap_or_else(|_| e.path().to_path_buf());
+            if let Ok(file) = path.strip_prefix(&out) {
+                let display = root.join(file);
+                result.insert(path, display);
+            }
+        }
+    }
+    result
+}
+
+/// Returns a list of files and line numbers to ignore (not indexes!).
+/// Generated sources are analysed under the path they're reported under
+pub fn get_line_analysis(
+    project: &Workspace,
+    config: &Config,
+    generated: &HashMap<PathBuf, PathBuf>,
+) -> HashMap<PathBuf, LineAnalysis> {
     let mut result: HashMap<PathBuf, LineAnalysis> = HashMap::new();
 
     let mut ignored_files: HashSet<PathBuf> = HashSet::new();
@@ -187,6 +363,13 @@ pub fn get_line_analysis(project: &Workspace, config: &Config) -> HashMap<PathBu
         analysis.ignore_all();
         result.insert(e.to_path_buf(), analysis);
     }
+    for (source, display) in generated {
+        let mut ignored = HashSet::new();
+        analyse_package(source, project.root(), config, &mut result, &mut ignored);
+        if let Some(analysis) = result.remove(source) {
+            result.insert(display.to_path_buf(), analysis);
+        }
+    }
     result
 }
 
@@ -234,7 +417,7 @@ fn analyse_package(
 ) {
     if let Some(file) = path.to_str() {
         let skip_cause_test = config.ignore_tests && path.starts_with(root.join("tests"));
-        let skip_cause_example = path.starts_with(root.join("examples"));
+        let skip_cause_example = !config.examples && path.starts_with(root.join("examples"));
         if !(skip_cause_test || skip_cause_example) {
             let file = File::open(file);
             if let Ok(mut file) = file {
@@ -250,8 +433,10 @@ fn analyse_package(
                         ignore_mods: RefCell::new(HashSet::new()),
                     };
 
-                    find_ignorable_lines(&content, &mut analysis);
                     process_items(&file.items, &ctx, &mut analysis);
+                    // After the items so lines ignored with comment markers
+                    // aren't covered again by the item analysis
+                    find_ignorable_lines(&content, &mut analysis);
                     // Check there's no conflict!
                     result.insert(path.to_path_buf(), analysis);
 
@@ -279,17 +464,62 @@ fn analyse_package(
 
 /// Finds lines from the raw string which are ignorable.
 /// These are often things like close braces, semi colons that may regiser as
-/// false positives.
+/// false positives. Lines marked with `// tarpaulin: ignore-line` and
+/// everything between `// tarpaulin: ignore-start` and
+/// `// tarpaulin: ignore-end` are also ignored.
 fn find_ignorable_lines(content: &str, analysis: &mut LineAnalysis) {
-    let lines = content
+    let mut lines = content
         .lines()
         .enumerate()
         .filter(|&(_, x)| !x.chars().any(|x| !"(){}[]?;\t ,".contains(x)))
         .map(|(i, _)| i + 1)
         .collect::<Vec<usize>>();
+    lines.append(&mut find_marked_lines(content));
     analysis.add_to_ignore(&lines);
 }
 
+/// Finds the lines ignored by `// tarpaulin: ` comment markers. An
+/// `ignore-line` marker after code ignores its own line and on a line by
+/// itself ignores the next line. An `ignore-start` without a matching
+/// `ignore-end` ignores the rest of the file.
+fn find_marked_lines(content: &str) -> Vec<usize> {
+    lazy_static! {
+        static ref MARKER: Regex =
+            Regex::new(r"//\s*tarpaulin:\s*(ignore-start|ignore-end|ignore-line)\b").unwrap();
+    }
+    let mut result = vec![];
+    let mut in_region = false;
+    for (i, line) in content.lines().enumerate() {
+        let line_number = i + 1;
+        let marker = match MARKER.captures(line) {
+            Some(c) => c,
+            None => {
+                if in_region {
+                    result.push(line_number);
+                }
+                continue;
+            }
+        };
+        match &marker[1] {
+            "ignore-start" => {
+                in_region = true;
+                result.push(line_number);
+            }
+            "ignore-end" => {
+                in_region = false;
+                result.push(line_number);
+            }
+            _ => {
+                result.push(line_number);
+                if line.trim_start().starts_with("//") {
+                    result.push(line_number + 1);
+                }
+            }
+        }
+    }
+    result
+}
+
 fn process_items(items: &[Item], ctx: &Context, analysis: &mut LineAnalysis) -> SubResult {
     let mut res = SubResult::Ok;
     for item in items.iter() {
@@ -309,6 +539,9 @@ fn process_items(items: &[Item], ctx: &Context, analysis: &mut LineAnalysis) ->
             }
             Item::Trait(ref i) => visit_trait(&i, analysis, ctx),
             Item::Impl(ref i) => visit_impl(&i, analysis, ctx),
+            Item::Macro(ref i) if i.ident.is_some() && ctx.config.macro_coverage => {
+                visit_macro_rules(&i, ctx, analysis);
+            }
             Item::Macro(ref i) => {
                 if let SubResult::Unreachable = visit_macro_call(&i.mac, ctx, analysis) {
                     res = SubResult::Unreachable;
@@ -328,7 +561,7 @@ fn process_statements(stmts: &[Stmt], ctx: &Context, analysis: &mut LineAnalysis
         let res = match *stmt {
             Stmt::Item(ref i) => process_items(&[i.clone()], ctx, analysis),
             Stmt::Expr(ref i) | Stmt::Semi(ref i, _) => process_expr(&i, ctx, analysis),
-            _ => SubResult::Ok,
+            Stmt::Local(ref l) => visit_local(&l, ctx, analysis),
         };
         if let SubResult::Unreachable = res {
             unreachable = true;
@@ -575,12 +808,16 @@ fn process_expr(expr: &Expr, ctx: &Context, analysis: &mut LineAnalysis) -> SubR
         Expr::Macro(ref m) => visit_macro_call(&m.mac, ctx, analysis),
         Expr::Struct(ref s) => visit_struct_expr(&s, analysis),
         Expr::Unsafe(ref u) => visit_unsafe_block(&u, ctx, analysis),
-        Expr::Call(ref c) => visit_callable(&c, analysis),
-        Expr::MethodCall(ref m) => visit_methodcall(&m, analysis),
+        Expr::Call(ref c) => visit_callable(&c, ctx, analysis),
+        Expr::MethodCall(ref m) => visit_methodcall(&m, ctx, analysis),
+        Expr::Try(ref t) => process_expr(&t.expr, ctx, analysis),
         Expr::Match(ref m) => visit_match(&m, ctx, analysis),
-        Expr::Block(ref b) => visit_block(&b.block, ctx, analysis),
+        Expr::Block(ref b) => visit_expr_block(&b, ctx, analysis),
+        Expr::Closure(ref c) => visit_closure(&c, ctx, analysis),
         Expr::If(ref i) => visit_if(&i, ctx, analysis),
+        Expr::IfLet(ref i) => visit_if_let(&i, ctx, analysis),
         Expr::While(ref w) => visit_while(&w, ctx, analysis),
+        Expr::WhileLet(ref w) => visit_while_let(&w, ctx, analysis),
         Expr::ForLoop(ref f) => visit_for(&f, ctx, analysis),
         Expr::Loop(ref l) => visit_loop(&l, ctx, analysis),
         Expr::Return(ref r) => visit_return(&r, ctx, analysis),
@@ -605,6 +842,35 @@ fn visit_return(ret: &ExprReturn, ctx: &Context, analysis: &mut LineAnalysis) ->
     SubResult::Ok
 }
 
+fn visit_local(local: &Local, ctx: &Context, analysis: &mut LineAnalysis) -> SubResult {
+    if !check_attr_list(&local.attrs, ctx) {
+        analysis.ignore_span(local.span());
+    } else if let Some((_, ref init)) = local.init {
+        process_expr(&init, ctx, analysis);
+    }
+    // An unreachable initialiser doesn't make the statements after it unreachable
+    SubResult::Ok
+}
+
+fn visit_closure(closure: &ExprClosure, ctx: &Context, analysis: &mut LineAnalysis) -> SubResult {
+    if !check_attr_list(&closure.attrs, ctx) {
+        analysis.ignore_span(closure.span());
+    } else {
+        process_expr(&closure.body, ctx, analysis);
+    }
+    // The closure body being unreachable doesn't stop the closure being made
+    SubResult::Ok
+}
+
+fn visit_expr_block(block: &ExprBlock, ctx: &Context, analysis: &mut LineAnalysis) -> SubResult {
+    if check_attr_list(&block.attrs, ctx) {
+        visit_block(&block.block, ctx, analysis)
+    } else {
+        analysis.ignore_span(block.span());
+        SubResult::Ok
+    }
+}
+
 fn visit_block(block: &Block, ctx: &Context, analysis: &mut LineAnalysis) -> SubResult {
     if let SubResult::Unreachable = process_statements(&block.stmts, ctx, analysis) {
         analysis.ignore_span(block.span());
@@ -614,10 +880,70 @@ fn visit_block(block: &Block, ctx: &Context, analysis: &mut LineAnalysis) -> Sub
     }
 }
 
+/// Removes any parentheses surrounding an expression
+fn strip_parens(expr: &Expr) -> &Expr {
+    match *expr {
+        Expr::Paren(ref p) => strip_parens(&p.expr),
+        _ => expr,
+    }
+}
+
+/// Splits a chain of short-circuiting boolean operators into its operator and
+/// conditions in evaluation order. Conditions using a different operator to
+/// the chain are kept whole.
+fn get_conditions(expr: &Expr) -> Option<(Operator, Vec<&Expr>)> {
+    let operator = match *strip_parens(expr) {
+        Expr::Binary(ref b) => match b.op {
+            BinOp::And(_) => Operator::And,
+            BinOp::Or(_) => Operator::Or,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    let mut conditions = vec![];
+    flatten_conditions(expr, operator, &mut conditions);
+    Some((operator, conditions))
+}
+
+fn flatten_conditions<'a>(expr: &'a Expr, operator: Operator, conditions: &mut Vec<&'a Expr>) {
+    let expr = strip_parens(expr);
+    if let Expr::Binary(ref b) = *expr {
+        let same_op = match (b.op, operator) {
+            (BinOp::And(_), Operator::And) | (BinOp::Or(_), Operator::Or) => true,
+            _ => false,
+        };
+        if same_op {
+            flatten_conditions(&b.left, operator, conditions);
+            flatten_conditions(&b.right, operator, conditions);
+            return;
+        }
+    }
+    conditions.push(expr);
+}
+
+/// Gets the first line executed when entering an expression, for blocks this
+/// is the first statement rather than the opening brace.
+fn first_executed_line(expr: &Expr) -> Option<usize> {
+    match *expr {
+        Expr::Block(ref b) => b.block.stmts.first().map(|s| s.span().start().line),
+        _ => Some(expr.span().start().line),
+    }
+}
+
 fn visit_match(mat: &ExprMatch, ctx: &Context, analysis: &mut LineAnalysis) -> SubResult {
     // a match with some arms is unreachable iff all its arms are unreachable
     let mut reachable_arm = false;
+    let decision = mat.expr.span().start().line;
     for arm in &mat.arms {
+        if !check_attr_list(&arm.attrs, ctx) {
+            // Skipped arms can't be known to be unreachable
+            analysis.ignore_span(arm.span());
+            reachable_arm = true;
+            continue;
+        }
+        if let Some(taken) = first_executed_line(&arm.body) {
+            analysis.add_branch(taken, decision, taken);
+        }
         if let SubResult::Ok = process_expr(&arm.body, ctx, analysis) {
             reachable_arm = true
         }
@@ -633,6 +959,12 @@ fn visit_match(mat: &ExprMatch, ctx: &Context, analysis: &mut LineAnalysis) -> S
 fn visit_if(if_block: &ExprIf, ctx: &Context, analysis: &mut LineAnalysis) -> SubResult {
     // an if expression is unreachable iff both its branches are unreachable
     let mut reachable_arm = false;
+    if let Some(first) = if_block.then_branch.stmts.first() {
+        let decision = if_block.cond.span().start().line;
+        let taken = first.span().start().line;
+        analysis.add_branch(decision, decision, taken);
+        analysis.add_conditions(decision, taken, &if_block.cond);
+    }
     if let SubResult::Ok = visit_block(&if_block.then_branch, ctx, analysis) {
         reachable_arm = true;
     }
@@ -653,6 +985,12 @@ fn visit_if(if_block: &ExprIf, ctx: &Context, analysis: &mut LineAnalysis) -> Su
 }
 
 fn visit_while(whl: &ExprWhile, ctx: &Context, analysis: &mut LineAnalysis) -> SubResult {
+    if let Some(first) = whl.body.stmts.first() {
+        let decision = whl.cond.span().start().line;
+        let taken = first.span().start().line;
+        analysis.add_branch(decision, decision, taken);
+        analysis.add_conditions(decision, taken, &whl.cond);
+    }
     // a while block is unreachable iff its body is
     if let SubResult::Unreachable = visit_block(&whl.body, ctx, analysis) {
         analysis.ignore_span(whl.span());
@@ -662,6 +1000,46 @@ fn visit_while(whl: &ExprWhile, ctx: &Context, analysis: &mut LineAnalysis) -> S
     }
 }
 
+fn visit_if_let(if_let: &ExprIfLet, ctx: &Context, analysis: &mut LineAnalysis) -> SubResult {
+    if let Some(first) = if_let.then_branch.stmts.first() {
+        let decision = if_let.expr.span().start().line;
+        analysis.add_branch(decision, decision, first.span().start().line);
+    }
+    process_expr(&if_let.expr, ctx, analysis);
+    // Like an if expression it's unreachable iff both branches are
+    let mut reachable_arm = false;
+    if let SubResult::Ok = visit_block(&if_let.then_branch, ctx, analysis) {
+        reachable_arm = true;
+    }
+    if let Some((_, ref else_block)) = if_let.else_branch {
+        if let SubResult::Ok = process_expr(&else_block, ctx, analysis) {
+            reachable_arm = true;
+        }
+    } else {
+        reachable_arm = true;
+    }
+    if !reachable_arm {
+        analysis.ignore_span(if_let.span());
+        SubResult::Unreachable
+    } else {
+        SubResult::Ok
+    }
+}
+
+fn visit_while_let(whl: &ExprWhileLet, ctx: &Context, analysis: &mut LineAnalysis) -> SubResult {
+    if let Some(first) = whl.body.stmts.first() {
+        let decision = whl.expr.span().start().line;
+        analysis.add_branch(decision, decision, first.span().start().line);
+    }
+    process_expr(&whl.expr, ctx, analysis);
+    if let SubResult::Unreachable = visit_block(&whl.body, ctx, analysis) {
+        analysis.ignore_span(whl.span());
+        SubResult::Unreachable
+    } else {
+        SubResult::Ok
+    }
+}
+
 fn visit_for(for_loop: &ExprForLoop, ctx: &Context, analysis: &mut LineAnalysis) -> SubResult {
     // a for block is unreachable iff its body is
     if let SubResult::Unreachable = visit_block(&for_loop.body, ctx, analysis) {
@@ -682,42 +1060,89 @@ fn visit_loop(loopex: &ExprLoop, ctx: &Context, analysis: &mut LineAnalysis) ->
     }
 }
 
-fn get_coverable_args(args: &Punctuated<Expr, Comma>) -> HashSet<usize> {
+/// Visits the arguments of a call and gets the lines with code that's run
+/// separately from the call. Closure bodies are their own coverable region
+/// and other arguments apart from literals keep their lines.
+fn visit_args(
+    args: &Punctuated<Expr, Comma>,
+    ctx: &Context,
+    analysis: &mut LineAnalysis,
+) -> HashSet<usize> {
     let mut lines: HashSet<usize> = HashSet::new();
     for a in args.iter() {
-        let s = a.span();
-        match *a {
-            Expr::Lit(_) => {}
-            _ => {
-                for i in s.start().line..(s.end().line + 1) {
-                    lines.insert(i);
-                }
+        let (start, end) = match *a {
+            Expr::Lit(_) => continue,
+            Expr::Closure(ref c) => {
+                visit_closure(&c, ctx, analysis);
+                closure_body_lines(&c)
             }
+            Expr::Call(_) | Expr::MethodCall(_) | Expr::Try(_) => {
+                process_expr(a, ctx, analysis);
+                (a.span().start().line, a.span().end().line)
+            }
+            _ => (a.span().start().line, a.span().end().line),
+        };
+        for i in start..(end + 1) {
+            lines.insert(i);
         }
     }
     lines
 }
 
-fn visit_callable(call: &ExprCall, analysis: &mut LineAnalysis) -> SubResult {
-    let start = call.span().start().line + 1;
+/// Gets the first and last lines of the code in a closure body. For a block
+/// these are from its statements so the line with the closure head can be
+/// merged with the call it's an argument of
+fn closure_body_lines(closure: &ExprClosure) -> (usize, usize) {
+    if let Expr::Block(ref b) = *closure.body {
+        if let (Some(first), Some(last)) = (b.block.stmts.first(), b.block.stmts.last()) {
+            return (first.span().start().line, last.span().end().line);
+        }
+    }
+    let span = closure.body.span();
+    (span.start().line, span.end().line)
+}
+
+fn visit_callable(call: &ExprCall, ctx: &Context, analysis: &mut LineAnalysis) -> SubResult {
+    let head = call.span().start().line;
     let end = call.span().end().line + 1;
-    let lines = get_coverable_args(&call.args);
-    let lines = (start..end)
+    let lines = visit_args(&call.args, ctx, analysis);
+    let lines = (head + 1..end)
         .filter(|x| !lines.contains(&x))
         .collect::<Vec<_>>();
-    analysis.add_to_ignore(&lines);
+    analysis.merge_lines(&lines, head);
     // We can't guess if a callable would actually be unreachable
     SubResult::Ok
 }
 
-fn visit_methodcall(meth: &ExprMethodCall, analysis: &mut LineAnalysis) -> SubResult {
-    let start = meth.span().start().line + 1;
+fn visit_methodcall(
+    meth: &ExprMethodCall,
+    ctx: &Context,
+    analysis: &mut LineAnalysis,
+) -> SubResult {
+    let head = meth.span().start().line;
     let end = meth.span().end().line + 1;
-    let lines = get_coverable_args(&meth.args);
-    let lines = (start..end)
+    // Walk down the chain of calls to the receiver at its head visiting the
+    // arguments of each call
+    let mut lines = visit_args(&meth.args, ctx, analysis);
+    let mut receiver = &meth.receiver;
+    loop {
+        match **receiver {
+            Expr::MethodCall(ref m) => {
+                lines.extend(visit_args(&m.args, ctx, analysis));
+                receiver = &m.receiver;
+            }
+            Expr::Try(ref t) => receiver = &t.expr,
+            Expr::Call(ref c) => {
+                lines.extend(visit_args(&c.args, ctx, analysis));
+                break;
+            }
+            _ => break,
+        }
+    }
+    let lines = (head + 1..end)
         .filter(|x| !lines.contains(&x))
         .collect::<Vec<_>>();
-    analysis.add_to_ignore(&lines);
+    analysis.merge_lines(&lines, head);
     // We can't guess if a method would actually be unreachable
     SubResult::Ok
 }
@@ -807,6 +1232,119 @@ fn visit_macro_call(mac: &Macro, ctx: &Context, analysis: &mut LineAnalysis) ->
     SubResult::Ok
 }
 
+/// Makes the lines of code in the arms of a `macro_rules!` definition
+/// coverable and ignores the rest of it. Only used when code expanded from
+/// macros is attributed to their definitions, otherwise none of it is hit
+fn visit_macro_rules(mac: &ItemMacro, ctx: &Context, analysis: &mut LineAnalysis) {
+    if !check_attr_list(&mac.attrs, ctx) {
+        analysis.ignore_span(mac.span());
+        return;
+    }
+    let mut cover = HashSet::new();
+    // Arms are `(matcher) => {transcriber}` separated by semicolons
+    let mut is_transcriber = false;
+    for token in mac.mac.tts.clone() {
+        match token {
+            TokenTree::Punct(ref p) if p.as_char() == '>' => is_transcriber = true,
+            TokenTree::Group(ref g) if is_transcriber => {
+                let stream = g.stream();
+                let item_level = is_item_transcriber(&stream);
+                find_macro_code(stream, item_level, &mut cover);
+                is_transcriber = false;
+            }
+            _ => {}
+        }
+    }
+    let start = mac.span().start().line;
+    let end = mac.span().end().line;
+    let lines = (start..(end + 1))
+        .filter(|x| !cover.contains(x))
+        .collect::<Vec<_>>();
+    analysis.add_to_ignore(&lines);
+    for line in cover {
+        if !analysis.should_ignore(line) {
+            analysis.cover.insert(line);
+        }
+    }
+}
+
+/// Returns true if a macro transcriber expands to items rather than
+/// statements or expressions
+fn is_item_transcriber(tokens: &TokenStream) -> bool {
+    let mut after_hash = false;
+    let mut after_unsafe = false;
+    for token in tokens.clone() {
+        match token {
+            TokenTree::Ident(ref i) => {
+                let item = [
+                    "fn", "pub", "struct", "enum", "union", "impl", "trait", "mod", "use", "const",
+                    "static", "type", "extern",
+                ]
+                .iter()
+                .any(|x| i == x)
+                    || i == "macro_rules";
+                if i == "unsafe" && !after_unsafe {
+                    // Could be an unsafe block or an unsafe fn, impl or trait
+                    after_unsafe = true;
+                    continue;
+                }
+                return item;
+            }
+            // Attributes can go on items or statements
+            TokenTree::Group(_) if after_hash => after_hash = false,
+            TokenTree::Group(ref g) => return !after_unsafe && is_item_transcriber(&g.stream()),
+            TokenTree::Punct(ref p) => after_hash = p.as_char() == '#',
+            TokenTree::Literal(_) => return false,
+        }
+    }
+    false
+}
+
+/// Finds the lines of a macro transcriber with code that's executed. In
+/// transcribers expanding to items only functions are executed
+fn find_macro_code(tokens: TokenStream, item_level: bool, cover: &mut HashSet<usize>) {
+    let mut after_fn = false;
+    let mut after_hash = false;
+    for token in tokens {
+        match token {
+            TokenTree::Group(ref g) => {
+                if after_hash {
+                    // Attributes aren't code
+                } else if item_level && after_fn && g.delimiter() == Delimiter::Brace {
+                    find_macro_code(g.stream(), false, cover);
+                    after_fn = false;
+                } else {
+                    find_macro_code(g.stream(), item_level, cover);
+                }
+                after_hash = false;
+            }
+            TokenTree::Ident(ref i) => {
+                if !item_level {
+                    cover.insert(i.span().start().line);
+                } else if i == "fn" {
+                    // The function entry is a breakpoint
+                    cover.insert(i.span().start().line);
+                    after_fn = true;
+                }
+                after_hash = false;
+            }
+            TokenTree::Literal(ref l) => {
+                if !item_level {
+                    cover.insert(l.span().start().line);
+                }
+                after_hash = false;
+            }
+            TokenTree::Punct(ref p) => {
+                after_hash = p.as_char() == '#';
+                if p.as_char() == ';' {
+                    // Function declaration without a body
+                    after_fn = false;
+                }
+            }
+        }
+    }
+}
+
 fn process_mac_args(tokens: &TokenStream) -> HashSet<usize> {
     let mut cover: HashSet<usize> = HashSet::new();
     // IntoIter not implemented for &TokenStream.
@@ -829,6 +1367,22 @@ mod tests {
     use super::*;
     use syn::parse_file;
 
+    #[test]
+    fn out_dir_packages() {
+        let package = |x: &str| out_dir_package(Path::new(x));
+        assert_eq!(
+            package("debug/build/my-crate-0123abcd/out"),
+            Some("my-crate".to_string())
+        );
+        assert_eq!(
+            package("x86_64-unknown-linux-gnu/release/build/foo-fedcba98/out"),
+            Some("foo".to_string())
+        );
+        assert_eq!(package("debug/build/foo-fedcba98/out/nested"), None);
+        assert_eq!(package("debug/build/foo-fedcba98"), None);
+        assert_eq!(package("debug/foo-fedcba98/out"), None);
+    }
+
     #[test]
     fn line_analysis_works() {
         let mut la = LineAnalysis::new();
@@ -1341,6 +1895,50 @@ mod tests {
         assert!(!lines.ignore.contains(&Lines::Line(19)));
     }
 
+    #[test]
+    fn merge_method_chains() {
+        let config = Config::default();
+        let mut lines = LineAnalysis::new();
+        let ctx = Context {
+            config: &config,
+            file_contents: "fn foo(v: &[u32]) -> u32 {
+                let total = v
+                    .iter()
+                    .map(|x| x * 2)
+                    .filter(|x| {
+                        x % 3 == 0          //6
+                    })
+                    .sum();
+                if let Some(x) = v
+                    .first()                //10
+                {
+                    total + x
+                } else {
+                    total
+                }
+            }",
+            file: Path::new(""),
+            ignore_mods: RefCell::new(HashSet::new()),
+        };
+        let parser = parse_file(ctx.file_contents).unwrap();
+        process_items(&parser.items, &ctx, &mut lines);
+        for l in &[3, 5, 7, 8] {
+            assert!(lines.ignore.contains(&Lines::Line(*l)), "line {}", l);
+            assert_eq!(lines.merged_line(*l), 2);
+        }
+        // Closure bodies are their own regions
+        assert!(!lines.ignore.contains(&Lines::Line(4)));
+        assert!(!lines.ignore.contains(&Lines::Line(6)));
+        assert_eq!(lines.merged_line(4), 4);
+        assert_eq!(lines.merged_line(6), 6);
+        assert_eq!(lines.merged_line(10), 9);
+        assert!(lines.branches.contains(&BranchLines {
+            line: 9,
+            decision: 9,
+            taken: 12,
+        }));
+    }
+
     #[test]
     fn filter_use_statements() {
         let config = Config::default();
@@ -1771,4 +2369,260 @@ mod tests {
         assert!(lines.ignore.contains(&Lines::Line(7)));
     }
 
+    #[test]
+    fn find_branches() {
+        let config = Config::default();
+        let mut lines = LineAnalysis::new();
+        let ctx = Context {
+            config: &config,
+            file_contents: "fn branches(mut x: u32) -> u32 {
+                if x > 5 {
+                    return 1;
+                }
+                while x < 3 {
+                    x += 1;
+                }
+                match x {
+                    0 => 2,
+                    _ => {
+                        3
+                    }
+                }
+            }",
+            file: Path::new(""),
+            ignore_mods: RefCell::new(HashSet::new()),
+        };
+        let parser = parse_file(ctx.file_contents).unwrap();
+        process_items(&parser.items, &ctx, &mut lines);
+        let expected = [(2, 2, 3), (5, 5, 6), (9, 8, 9), (11, 8, 11)];
+        assert_eq!(lines.branches.len(), expected.len());
+        for &(line, decision, taken) in &expected {
+            assert!(lines.branches.contains(&BranchLines {
+                line,
+                decision,
+                taken
+            }));
+        }
+
+        let mut lines = LineAnalysis::new();
+        let ctx = Context {
+            config: &config,
+            file_contents: "fn single_line(x: bool) -> u32 {\nif x { 1 } else { 2 }\n}",
+            file: Path::new(""),
+            ignore_mods: RefCell::new(HashSet::new()),
+        };
+        let parser = parse_file(ctx.file_contents).unwrap();
+        process_items(&parser.items, &ctx, &mut lines);
+        assert!(lines.branches.is_empty());
+    }
+
+    #[test]
+    fn find_conditions() {
+        let config = Config::default();
+        let mut lines = LineAnalysis::new();
+        let ctx = Context {
+            config: &config,
+            file_contents: "fn conditions(a: bool, b: bool, c: bool) {
+                if a &&
+                    (b || c) &&
+                    !c {
+                    println!(\"yes\");
+                }
+                while a || b {
+                    println!(\"no\");
+                }
+                if a {
+                    println!(\"maybe\");
+                }
+            }",
+            file: Path::new(""),
+            ignore_mods: RefCell::new(HashSet::new()),
+        };
+        let parser = parse_file(ctx.file_contents).unwrap();
+        process_items(&parser.items, &ctx, &mut lines);
+        assert_eq!(lines.conditions.len(), 2);
+        assert!(lines.conditions.contains(&ConditionLines {
+            line: 2,
+            taken: 5,
+            operator: Operator::And,
+            conditions: vec![(2, 20), (3, 22), (4, 21)],
+        }));
+        assert!(lines.conditions.contains(&ConditionLines {
+            line: 7,
+            taken: 8,
+            operator: Operator::Or,
+            conditions: vec![(7, 23), (7, 28)],
+        }));
+    }
+
+    #[test]
+    fn tarpaulin_skip_statements() {
+        let config = Config::default();
+        let mut lines = LineAnalysis::new();
+        let ctx = Context {
+            config: &config,
+            file_contents: "fn foo(x: Option<u32>) {
+                #[cfg_attr(tarpaulin, skip)]
+                let y = x
+                    .unwrap_or(0);
+                match x {
+                    Some(_) => println!(\"some\"),
+                    #[cfg_attr(tarpaulin, skip)]
+                    None => println!(\"none\"),
+                }
+                #[cfg_attr(tarpaulin, skip)]
+                {
+                    println!(\"block\");
+                }
+                println!(\"covered\");
+            }",
+            file: Path::new(""),
+            ignore_mods: RefCell::new(HashSet::new()),
+        };
+        let parser = parse_file(ctx.file_contents).unwrap();
+        process_items(&parser.items, &ctx, &mut lines);
+        assert!(lines.ignore.contains(&Lines::Line(3)));
+        assert!(lines.ignore.contains(&Lines::Line(4)));
+        assert!(!lines.ignore.contains(&Lines::Line(6)));
+        assert!(lines.ignore.contains(&Lines::Line(8)));
+        assert!(lines.ignore.contains(&Lines::Line(12)));
+        assert!(!lines.ignore.contains(&Lines::Line(14)));
+        assert_eq!(lines.branches.len(), 1);
+    }
+
+    #[test]
+    fn tarpaulin_skip_in_initialisers() {
+        let config = Config::default();
+        let mut lines = LineAnalysis::new();
+        let ctx = Context {
+            config: &config,
+            file_contents: "fn foo(x: Option<u32>) {
+                let y = match x {
+                    Some(i) => i,
+                    #[cfg_attr(tarpaulin, skip)]
+                    None => {
+                        println!(\"none\");
+                        0
+                    }
+                };
+                let z = if y > 1 && y < 5 {
+                    2
+                } else {
+                    3
+                };
+                let w = #[cfg_attr(tarpaulin, skip)] {
+                    println!(\"block\");
+                };
+            }",
+            file: Path::new(""),
+            ignore_mods: RefCell::new(HashSet::new()),
+        };
+        let parser = parse_file(ctx.file_contents).unwrap();
+        process_items(&parser.items, &ctx, &mut lines);
+        assert!(!lines.ignore.contains(&Lines::Line(3)));
+        for l in 4..8 {
+            assert!(lines.ignore.contains(&Lines::Line(l)), "line {}", l);
+        }
+        assert!(lines.ignore.contains(&Lines::Line(16)));
+        // The match arm which isn't skipped and the if are both branches
+        assert_eq!(lines.branches.len(), 2);
+        assert_eq!(lines.conditions.len(), 1);
+    }
+
+    #[test]
+    fn tarpaulin_skip_closures() {
+        let config = Config::default();
+        let mut lines = LineAnalysis::new();
+        let ctx = Context {
+            config: &config,
+            file_contents: "fn foo() {
+                let f = #[cfg_attr(tarpaulin, skip)] |x: u32| {
+                    println!(\"{}\", x);
+                };
+                let g = |x: u32| {
+                    println!(\"{}\", x);
+                };
+            }",
+            file: Path::new(""),
+            ignore_mods: RefCell::new(HashSet::new()),
+        };
+        let parser = parse_file(ctx.file_contents).unwrap();
+        process_items(&parser.items, &ctx, &mut lines);
+        assert!(lines.ignore.contains(&Lines::Line(2)));
+        assert!(lines.ignore.contains(&Lines::Line(3)));
+        assert!(!lines.ignore.contains(&Lines::Line(5)));
+        assert!(!lines.ignore.contains(&Lines::Line(6)));
+    }
+
+    #[test]
+    fn ignore_comment_markers() {
+        let mut lines = LineAnalysis::new();
+        let content = "fn foo() {
+            let x = 1; // tarpaulin: ignore-line
+            // tarpaulin: ignore-line
+            let y = 2;
+            let z = 3;
+            // tarpaulin: ignore-start
+            if x > y {
+                panic!(\"unexpected\");
+            }
+            // tarpaulin: ignore-end
+            println!(\"{}\", z);
+            //tarpaulin:ignore-start
+            println!(\"never ends\");
+        }";
+        find_ignorable_lines(content, &mut lines);
+        for l in &[2, 3, 4, 6, 7, 8, 9, 10, 12, 13, 14] {
+            assert!(lines.ignore.contains(&Lines::Line(*l)), "line {}", l);
+        }
+        assert!(!lines.ignore.contains(&Lines::Line(1)));
+        assert!(!lines.ignore.contains(&Lines::Line(5)));
+        assert!(!lines.ignore.contains(&Lines::Line(11)));
+    }
+
+    #[test]
+    fn cover_macro_rules() {
+        let mut config = Config::default();
+        config.macro_coverage = true;
+        let mut lines = LineAnalysis::new();
+        let ctx = Context {
+            config: &config,
+            file_contents: "macro_rules! double {
+                ($x:expr) => {
+                    $x * 2
+                };
+                ($x:expr, $y:expr) => {{
+                    let a = $x;
+                    a + $y
+                }};
+            }
+            macro_rules! make_fn {
+                ($name:ident) => {
+                    #[inline(never)]
+                    pub fn $name() -> u32 {
+                        42
+                    }
+                };
+            }",
+            file: Path::new(""),
+            ignore_mods: RefCell::new(HashSet::new()),
+        };
+        let parser = parse_file(ctx.file_contents).unwrap();
+        process_items(&parser.items, &ctx, &mut lines);
+        for l in &[3, 6, 7, 13, 14] {
+            assert!(lines.cover.contains(l), "line {}", l);
+        }
+        for l in &[1, 2, 5, 10, 11, 12] {
+            assert!(lines.ignore.contains(&Lines::Line(*l)), "line {}", l);
+        }
+
+        let config = Config::default();
+        let mut lines = LineAnalysis::new();
+        let ctx = Context {
+            config: &config,
+            ..ctx
+        };
+        process_items(&parser.items, &ctx, &mut lines);
+        assert!(!lines.cover.contains(&3));
+    }
 }
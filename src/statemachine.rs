@@ -2,14 +2,21 @@ use crate::breakpoint::*;
 use crate::config::Config;
 use crate::errors::RunError;
 use crate::ptrace_control::*;
+use crate::test_loader::generate_tracemap;
 use crate::traces::*;
-use log::debug;
+use cargo::core::Workspace;
+use log::{debug, info, warn};
 use nix::errno::Errno;
+use nix::libc::AT_ENTRY;
 use nix::sys::signal::Signal;
 use nix::sys::wait::*;
 use nix::unistd::Pid;
 use nix::Error as NixErr;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::mem;
+use std::path::PathBuf;
 use std::time::Instant;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -109,31 +116,195 @@ impl TestState {
 pub fn create_state_machine<'a>(
     test: Pid,
     traces: &'a mut TraceMap,
+    project: &'a Workspace<'a>,
+    executables: &'a HashSet<PathBuf>,
     config: &'a Config,
 ) -> (TestState, LinuxData<'a>) {
-    let mut data = LinuxData::new(traces, config);
+    let mut data = LinuxData::new(traces, project, executables, config);
     data.parent = test;
     (TestState::start_state(), data)
 }
 
+/// Reads a little endian integer from the start of the bytes
+fn read_le(bytes: &[u8], size: usize) -> u64 {
+    bytes
+        .iter()
+        .take(size)
+        .rev()
+        .fold(0, |acc, b| (acc << 8) | u64::from(*b))
+}
+
+/// Gets the offset the executable of a process is loaded at from the addresses
+/// in its debug information. Position independent executables are loaded at a
+/// random address so this is found from the entry point the kernel gave the
+/// process in its auxiliary vector and the entry point in the ELF header.
+fn load_bias(pid: Pid) -> io::Result<u64> {
+    let word = mem::size_of::<usize>();
+    let auxv = fs::read(format!("/proc/{}/auxv", pid))?;
+    let entry = auxv
+        .chunks(2 * word)
+        .filter(|x| x.len() == 2 * word)
+        .find(|x| read_le(x, word) == AT_ENTRY as u64)
+        .map(|x| read_le(&x[word..], word))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no AT_ENTRY in auxv"))?;
+
+    let mut header = [0u8; 32];
+    File::open(format!("/proc/{}/exe", pid))?.read_exact(&mut header)?;
+    if header[..4] != *b"\x7fELF" {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "executable isn't an ELF file",
+        ));
+    }
+    // e_entry follows the 24 bytes of identification, type, machine and
+    // version and is the size of an address for the ELF class
+    let elf_entry = match header[4] {
+        2 => read_le(&header[24..], 8),
+        _ => read_le(&header[24..], 4),
+    };
+    Ok(entry.wrapping_sub(elf_entry))
+}
+
+/// Inserts a breakpoint into the process for every address needing coverage
+/// data. Addresses are from the debug information so are moved by the load
+/// bias of the executable. Returns the breakpoints and the addresses which
+/// need hit counts, both at the addresses in the running process. If any
+/// breakpoint can't be inserted the ones already inserted are removed.
+fn instrument(
+    pid: Pid,
+    traces: &TraceMap,
+    config: &Config,
+    bias: u64,
+) -> Result<(HashMap<u64, Breakpoint>, HashSet<u64>), RunError> {
+    let mut breakpoints = HashMap::new();
+    let mut branch_addresses = if config.branch_coverage {
+        traces.branch_addresses()
+    } else {
+        HashSet::new()
+    };
+    let mut addresses = traces
+        .all_traces()
+        .iter()
+        .filter_map(|x| x.address)
+        .collect::<Vec<_>>();
+    if config.condition_coverage {
+        let conditions = traces.condition_addresses();
+        // Condition addresses may not be the start of a line so need their
+        // own breakpoints
+        addresses.extend(conditions.iter().cloned());
+        branch_addresses.extend(conditions);
+    }
+    let branch_addresses = branch_addresses
+        .into_iter()
+        .map(|x| x.wrapping_add(bias))
+        .collect();
+    for addr in addresses {
+        let addr = addr.wrapping_add(bias);
+        let error = match Breakpoint::new(pid, addr) {
+            Ok(bp) => {
+                let _ = breakpoints.insert(addr, bp);
+                continue;
+            }
+            Err(NixErr::UnsupportedOperation) => {
+                debug!("Instrumentation address clash, ignoring 0x{:x}", addr);
+                continue;
+            }
+            Err(e) if e == NixErr::Sys(Errno::EIO) => RunError::TestRuntime(format!(
+                "ERROR: Tarpaulin cannot find code address 0x{:x}, \
+                 the debug information may not match the executable",
+                addr
+            )),
+            Err(_) => RunError::TestRuntime("Failed to instrument test executable".to_string()),
+        };
+        for bp in breakpoints.values() {
+            let _ = bp.disable(pid);
+        }
+        return Err(error);
+    }
+    Ok((breakpoints, branch_addresses))
+}
+
+/// A workspace executable exec'd by the tests
+struct ExecImage {
+    /// Offset the executable is loaded at from the addresses in its debug
+    /// information
+    bias: u64,
+    /// Map of addresses to breakpoints
+    breakpoints: HashMap<u64, Breakpoint>,
+    /// Addresses which need hit counts to resolve branch and condition
+    /// coverage
+    branch_addresses: HashSet<u64>,
+    /// Coverage of the executable, merged into the test coverage once no
+    /// process is running it
+    traces: TraceMap,
+}
+
+/// A process being traced, either the test or a process forked from it. Hits
+/// are counted per process and added to the coverage when the process ends.
+struct TracedProcess {
+    /// Exec'd executable the process is running, None if it's running the
+    /// test executable
+    image: Option<usize>,
+    /// Number of times each breakpoint has been hit in the process, keyed by
+    /// the address in the debug information
+    hits: HashMap<u64, u64>,
+    /// Process which vforked this one. Until it execs or exits a vforked
+    /// process runs in its parent's memory, so its hits are the parent's
+    vfork_parent: Option<Pid>,
+}
+
+impl TracedProcess {
+    fn new(image: Option<usize>) -> Self {
+        TracedProcess {
+            image,
+            hits: HashMap::new(),
+            vfork_parent: None,
+        }
+    }
+}
+
 /// Handle to linux process state
 pub struct LinuxData<'a> {
     /// Recent result from waitpid to be handled by statemachine
     wait: WaitStatus,
+    /// Results from waitpid received while stopping threads, these are
+    /// handled before waiting for anything new
+    pending: VecDeque<WaitStatus>,
     /// Current Pid to process
     current: Pid,
     /// Parent PID of test process
     parent: Pid,
+    /// Offset the test executable is loaded at from the addresses in its
+    /// debug information
+    bias: u64,
     /// Map of addresses to breakpoints
     breakpoints: HashMap<u64, Breakpoint>,
+    /// Addresses which need hit counts to resolve branch and condition
+    /// coverage
+    branch_addresses: HashSet<u64>,
     /// Instrumentation points in code with associated coverage data
     traces: &'a mut TraceMap,
     /// Program config
     config: &'a Config,
-    /// Thread count. Hopefully getting rid of in future
-    thread_count: isize,
-    /// Used to show anomalies noticed so hit counts disabled
-    force_disable_hit_count: bool,
+    /// Traced processes mapped to their process ID
+    processes: HashMap<Pid, TracedProcess>,
+    /// Every traced thread mapped to the ID of its process
+    threads: HashMap<Pid, Pid>,
+    /// Threads or processes which stopped before the event announcing them
+    /// arrived. They're left stopped until it does so they can't run through
+    /// a breakpoint while the rest of their process is stopped
+    held: HashSet<Pid>,
+    /// Workspace the test belongs to
+    project: &'a Workspace<'a>,
+    /// Binaries built from the workspace for this run. Tests exec'ing one of
+    /// these are followed into it
+    executables: &'a HashSet<PathBuf>,
+    /// Workspace executables exec'd by the test
+    exec_images: HashMap<usize, ExecImage>,
+    /// ID to give the next exec'd executable
+    next_image: usize,
+    /// Tracemaps of exec'd executables so each is only analysed once
+    exec_tracemaps: HashMap<PathBuf, TraceMap>,
 }
 
 impl<'a> StateData for LinuxData<'a> {
@@ -159,32 +330,16 @@ impl<'a> StateData for LinuxData<'a> {
 
     fn init(&mut self) -> Result<TestState, RunError> {
         trace_children(self.current)?;
-        for trace in self.traces.all_traces() {
-            if let Some(addr) = trace.address {
-                match Breakpoint::new(self.current, addr) {
-                    Ok(bp) => {
-                        let _ = self.breakpoints.insert(addr, bp);
-                    }
-                    Err(e) if e == NixErr::Sys(Errno::EIO) => {
-                        return Err(RunError::TestRuntime(
-                            "ERROR: Tarpaulin cannot find code addresses \
-                             check that pie is disabled for your linker. \
-                             If linking with gcc try adding -C link-args=-no-pie \
-                             to your rust flags"
-                                .to_string(),
-                        ));
-                    }
-                    Err(NixErr::UnsupportedOperation) => {
-                        debug!("Instrumentation address clash, ignoring 0x{:x}", addr);
-                    }
-                    Err(_) => {
-                        return Err(RunError::TestRuntime(
-                            "Failed to instrument test executable".to_string(),
-                        ));
-                    }
-                }
-            }
-        }
+        self.processes
+            .insert(self.current, TracedProcess::new(None));
+        self.threads.insert(self.current, self.current);
+        self.bias = load_bias(self.current).map_err(|e| {
+            RunError::TestRuntime(format!("Failed to find where the test is loaded: {}", e))
+        })?;
+        let (breakpoints, branch_addresses) =
+            instrument(self.current, self.traces, self.config, self.bias)?;
+        self.breakpoints = breakpoints;
+        self.branch_addresses = branch_addresses;
 
         if continue_exec(self.parent, None).is_ok() {
             return Ok(TestState::wait_state());
@@ -196,6 +351,10 @@ impl<'a> StateData for LinuxData<'a> {
     }
 
     fn wait(&mut self) -> Result<Option<TestState>, RunError> {
+        if let Some(s) = self.pending.pop_front() {
+            self.wait = s;
+            return Ok(Some(TestState::Stopped));
+        }
         let wait = waitpid(
             Pid::from_raw(-1),
             Some(WaitPidFlag::WNOHANG | WaitPidFlag::__WALL),
@@ -235,6 +394,10 @@ impl<'a> StateData for LinuxData<'a> {
                     ))),
                 }
             }
+            WaitStatus::Stopped(child, Signal::SIGSTOP) if !self.threads.contains_key(&child) => {
+                self.held.insert(child);
+                Ok(TestState::wait_state())
+            }
             WaitStatus::Stopped(child, Signal::SIGSTOP) => match continue_exec(child, None) {
                 Ok(_) => Ok(TestState::wait_state()),
                 Err(e) => Err(RunError::TestRuntime(format!(
@@ -264,10 +427,14 @@ impl<'a> StateData for LinuxData<'a> {
                 }
             }
             WaitStatus::Exited(child, ec) => {
-                for ref mut value in self.breakpoints.values_mut() {
-                    value.thread_killed(child);
-                }
+                self.threads.remove(&child);
+                self.end_process(child);
                 if child == self.parent {
+                    // Anything still running won't be waited for
+                    let processes = self.processes.keys().cloned().collect::<Vec<_>>();
+                    for process in processes {
+                        self.end_process(process);
+                    }
                     Ok(TestState::End(ec))
                 } else {
                     // Process may have already been destroyed. This is just incase
@@ -284,16 +451,30 @@ impl<'a> StateData for LinuxData<'a> {
 }
 
 impl<'a> LinuxData<'a> {
-    pub fn new(traces: &'a mut TraceMap, config: &'a Config) -> LinuxData<'a> {
+    pub fn new(
+        traces: &'a mut TraceMap,
+        project: &'a Workspace<'a>,
+        executables: &'a HashSet<PathBuf>,
+        config: &'a Config,
+    ) -> LinuxData<'a> {
         LinuxData {
             wait: WaitStatus::StillAlive,
+            pending: VecDeque::new(),
             current: Pid::from_raw(0),
             parent: Pid::from_raw(0),
+            bias: 0,
             breakpoints: HashMap::new(),
+            branch_addresses: HashSet::new(),
             traces,
             config,
-            thread_count: 0,
-            force_disable_hit_count: config.count,
+            processes: HashMap::new(),
+            threads: HashMap::new(),
+            held: HashSet::new(),
+            project,
+            executables,
+            exec_images: HashMap::new(),
+            next_image: 0,
+            exec_tracemaps: HashMap::new(),
         }
     }
 
@@ -308,8 +489,11 @@ impl<'a> LinuxData<'a> {
         if sig == Signal::SIGTRAP {
             match event {
                 PTRACE_EVENT_CLONE => {
-                    if get_event_data(child).is_ok() {
-                        self.thread_count += 1;
+                    if let Ok(tid) = get_event_data(child) {
+                        let tid = Pid::from_raw(tid as pid_t);
+                        let process = self.process_of(child);
+                        self.threads.insert(tid, process);
+                        self.release(tid)?;
                         continue_exec(child, None)?;
                         Ok(TestState::wait_state())
                     } else {
@@ -319,15 +503,36 @@ impl<'a> LinuxData<'a> {
                     }
                 }
                 PTRACE_EVENT_FORK | PTRACE_EVENT_VFORK => {
+                    if let Ok(pid) = get_event_data(child) {
+                        // The fork starts with a copy of the parents memory
+                        // so has the same breakpoints in place
+                        let pid = Pid::from_raw(pid as pid_t);
+                        let parent = self.process_of(child);
+                        let image = self.processes.get(&parent).and_then(|x| x.image);
+                        let mut process = TracedProcess::new(image);
+                        if event == PTRACE_EVENT_VFORK {
+                            process.vfork_parent = Some(parent);
+                        }
+                        self.processes.insert(pid, process);
+                        self.threads.insert(pid, pid);
+                        self.release(pid)?;
+                    }
                     continue_exec(child, None)?;
                     Ok(TestState::wait_state())
                 }
                 PTRACE_EVENT_EXEC => {
-                    detach_child(child)?;
+                    let process = self.process_of(child);
+                    self.end_process(process);
+                    if self.follow_exec(child)? {
+                        continue_exec(child, None)?;
+                    } else {
+                        self.threads.retain(|_, p| *p != process);
+                        detach_child(child)?;
+                    }
                     Ok(TestState::wait_state())
                 }
                 PTRACE_EVENT_EXIT => {
-                    self.thread_count -= 1;
+                    self.threads.remove(&child);
                     continue_exec(child, None)?;
                     Ok(TestState::wait_state())
                 }
@@ -341,32 +546,236 @@ impl<'a> LinuxData<'a> {
         }
     }
 
+    /// Resumes a new thread or process if it stopped before the event
+    /// announcing it was handled
+    fn release(&mut self, pid: Pid) -> Result<(), RunError> {
+        if self.held.remove(&pid) {
+            continue_exec(pid, None)?;
+        }
+        Ok(())
+    }
+
+    /// Gets the process a thread belongs to. Threads not yet known about are
+    /// assumed to be in the test process
+    fn process_of(&self, thread: Pid) -> Pid {
+        self.threads.get(&thread).cloned().unwrap_or(self.parent)
+    }
+
+    /// Starts tracing an exec'd executable if it's one of the workspace build
+    /// artifacts. Returns whether the executable is being traced.
+    fn follow_exec(&mut self, pid: Pid) -> Result<bool, RunError> {
+        let exe = match fs::read_link(format!("/proc/{}/exe", pid)) {
+            Ok(exe) => exe,
+            Err(_) => return Ok(false),
+        };
+        if !self.executables.contains(&exe) {
+            return Ok(false);
+        }
+        if !self.exec_tracemaps.contains_key(&exe) {
+            match generate_tracemap(self.project, &exe, self.config) {
+                Ok(t) => {
+                    self.exec_tracemaps.insert(exe.clone(), t);
+                }
+                Err(e) => {
+                    debug!("Not following exec of {}: {}", exe.display(), e);
+                    return Ok(false);
+                }
+            }
+        }
+        let bias = match load_bias(pid) {
+            Ok(b) => b,
+            Err(e) => {
+                debug!("Not following exec of {}: {}", exe.display(), e);
+                return Ok(false);
+            }
+        };
+        let traces = self.exec_tracemaps[&exe].clone();
+        let (breakpoints, branch_addresses) = match instrument(pid, &traces, self.config, bias) {
+            Ok(x) => x,
+            Err(e) => {
+                warn!("Not following exec of {}: {}", exe.display(), e);
+                return Ok(false);
+            }
+        };
+        info!("Following exec of {}", exe.display());
+        let image = self.next_image;
+        self.next_image += 1;
+        self.exec_images.insert(
+            image,
+            ExecImage {
+                bias,
+                breakpoints,
+                branch_addresses,
+                traces,
+            },
+        );
+        self.processes.insert(pid, TracedProcess::new(Some(image)));
+        self.threads.insert(pid, pid);
+        Ok(true)
+    }
+
+    /// Stops tracking a process, adding its hits to the coverage of the
+    /// executable it was running or to its parent if it was vforked. Once no
+    /// process is running an exec'd executable its coverage is merged into the
+    /// test coverage.
+    fn end_process(&mut self, pid: Pid) {
+        let process = match self.processes.remove(&pid) {
+            Some(p) => p,
+            None => return,
+        };
+        if let Some(parent) = process
+            .vfork_parent
+            .and_then(|x| self.processes.get_mut(&x))
+        {
+            for (&address, &hits) in &process.hits {
+                *parent.hits.entry(address).or_insert(0) += hits;
+            }
+            return;
+        }
+        let condition_coverage = self.config.condition_coverage;
+        let traces = match process.image {
+            Some(image) => match self.exec_images.get_mut(&image) {
+                Some(image) => &mut image.traces,
+                None => return,
+            },
+            None => &mut *self.traces,
+        };
+        for (&address, &hits) in &process.hits {
+            if let Some(ref mut t) = traces.get_trace_mut(address) {
+                if let CoverageStat::Line(ref mut x) = t.stats {
+                    *x += hits;
+                }
+            }
+            if condition_coverage {
+                traces.add_condition_hit(address, hits);
+            }
+        }
+        if let Some(image) = process.image {
+            if !self.processes.values().any(|x| x.image == Some(image)) {
+                if let Some(mut finished) = self.exec_images.remove(&image) {
+                    finished.traces.resolve_logic();
+                    self.traces.merge(&finished.traces);
+                }
+            }
+        }
+    }
+
+    /// Stops every thread of the process other than the current one. Returns
+    /// the threads stopped, any other events seen while waiting for them to
+    /// stop are stored to be handled later.
+    fn stop_other_threads(&mut self, process: Pid) -> Vec<Pid> {
+        // Threads with an event waiting to be handled are already stopped and
+        // won't take a SIGSTOP until they're resumed, so waiting on them
+        // would never return
+        let mut stopped_already = self
+            .pending
+            .iter()
+            .filter_map(|x| x.pid())
+            .collect::<HashSet<_>>();
+        stopped_already.insert(self.current);
+        let others = self
+            .threads
+            .iter()
+            .filter(|&(t, &p)| p == process && !stopped_already.contains(t))
+            .map(|(&t, _)| t)
+            .collect::<Vec<_>>();
+        let mut signalled = vec![];
+        for t in others {
+            if signal_thread(process, t, Signal::SIGSTOP).is_ok() {
+                signalled.push(t);
+            }
+        }
+        let mut stopped = vec![];
+        for t in signalled {
+            match waitpid(t, Some(WaitPidFlag::__WALL)) {
+                Ok(WaitStatus::Stopped(_, Signal::SIGSTOP)) => stopped.push(t),
+                // The SIGSTOP is still queued and will be seen once this event
+                // has been handled
+                Ok(s) => self.pending.push_back(s),
+                Err(_) => {
+                    self.threads.remove(&t);
+                }
+            }
+        }
+        stopped
+    }
+
+    /// Steps the current thread over the breakpoint at the given address in
+    /// the executable it's running. When the breakpoint will be reenabled the
+    /// other threads of the process are stopped first so none of them can run
+    /// past it uncounted. Returns whether the instruction at the breakpoint
+    /// ran, if a signal interrupted the step the breakpoint will be hit again.
+    fn step_over_breakpoint(
+        &mut self,
+        process: Pid,
+        image: Option<usize>,
+        rip: u64,
+        reenable: bool,
+    ) -> Result<bool, RunError> {
+        let stopped = if reenable {
+            self.stop_other_threads(process)
+        } else {
+            vec![]
+        };
+        let breakpoints = match image {
+            Some(i) => self.exec_images.get_mut(&i).map(|x| &mut x.breakpoints),
+            None => Some(&mut self.breakpoints),
+        };
+        let status = match breakpoints.and_then(|x| x.get_mut(&rip)) {
+            Some(bp) => bp.step_over(self.current, reenable),
+            None => Ok(WaitStatus::Stopped(self.current, Signal::SIGTRAP)),
+        };
+        for t in &stopped {
+            let _ = continue_exec(*t, None);
+        }
+        match status? {
+            WaitStatus::Stopped(_, Signal::SIGTRAP) => {
+                continue_exec(self.current, None)?;
+                Ok(true)
+            }
+            s @ WaitStatus::Stopped(..) => {
+                self.pending.push_back(s);
+                Ok(false)
+            }
+            s => {
+                self.pending.push_back(s);
+                Ok(true)
+            }
+        }
+    }
+
     fn collect_coverage_data(&mut self) -> Result<TestState, RunError> {
         if let Ok(rip) = current_instruction_pointer(self.current) {
             let rip = (rip - 1) as u64;
-            if self.breakpoints.contains_key(&rip) {
-                let bp = &mut self.breakpoints.get_mut(&rip).unwrap();
-                let enable = self.config.count && self.thread_count < 2;
-                if !enable && self.force_disable_hit_count {
-                    println!("Code is mulithreaded, disabling hit count");
-                    println!("Results may be improved by not using the '--count' option when running tarpaulin");
-                    self.force_disable_hit_count = false;
-                }
-                // Don't reenable if multithreaded as can't yet sort out segfault issue
-                let updated = if let Ok(x) = bp.process(self.current, enable) {
-                    x
-                } else {
-                    // So failed to process a breakpoint.. Still continue to avoid
-                    // stalling
-                    continue_exec(self.current, None)?;
-                    false
-                };
-                if updated {
-                    if let Some(ref mut t) = self.traces.get_trace_mut(rip) {
-                        if let CoverageStat::Line(ref mut x) = t.stats {
-                            *x += 1;
+            let process = self.process_of(self.current);
+            // Processes running an exec'd executable use its breakpoints
+            let image = self.processes.get(&process).and_then(|x| x.image);
+            let (is_breakpoint, reenable, bias) = {
+                let (breakpoints, branch_addresses, bias) =
+                    match image.and_then(|i| self.exec_images.get(&i)) {
+                        Some(image) => (&image.breakpoints, &image.branch_addresses, image.bias),
+                        None => (&self.breakpoints, &self.branch_addresses, self.bias),
+                    };
+                (
+                    breakpoints.contains_key(&rip),
+                    self.config.count || branch_addresses.contains(&rip),
+                    bias,
+                )
+            };
+            if is_breakpoint {
+                match self.step_over_breakpoint(process, image, rip, reenable) {
+                    Ok(true) => {
+                        if let Some(p) = self.processes.get_mut(&process) {
+                            *p.hits.entry(rip.wrapping_sub(bias)).or_insert(0) += 1;
                         }
                     }
+                    // The hit is counted when the breakpoint is hit again
+                    Ok(false) => {}
+                    Err(_) => {
+                        // So failed to process a breakpoint.. Still continue to avoid
+                        // stalling
+                        continue_exec(self.current, None)?;
+                    }
                 }
             } else {
                 continue_exec(self.current, None)?;
@@ -387,3 +796,17 @@ impl<'a> LinuxData<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn little_endian_reads() {
+        let bytes = [0x78, 0x56, 0x34, 0x12, 0xff, 0xff, 0xff, 0xff];
+        assert_eq!(read_le(&bytes, 4), 0x1234_5678);
+        assert_eq!(read_le(&bytes, 8), 0xffff_ffff_1234_5678);
+        // Fewer bytes than the size reads what's there
+        assert_eq!(read_le(&bytes[..2], 4), 0x5678);
+    }
+}
@@ -7,13 +7,13 @@ use log::debug;
 use memmap::MmapOptions;
 use object::{File as OFile, Object};
 use rustc_demangle::demangle;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io;
 use std::path::{Path, PathBuf};
 
-/// Describes a function as `low_pc`, `high_pc` and bool representing `is_test`.
-type FuncDesc = (u64, u64, FunctionType);
+/// Describes a function as `low_pc`, `high_pc`, its type and demangled name.
+type FuncDesc = (u64, u64, FunctionType, String);
 
 #[derive(Clone, Copy, PartialEq)]
 enum FunctionType {
@@ -66,6 +66,7 @@ where
     Offset: ReaderOffset,
 {
     let mut func_type = FunctionType::Standard;
+    let mut func_name = String::new();
     let low = die.attr_value(DW_AT_low_pc)?;
     let high = die.attr_value(DW_AT_high_pc)?;
     let linkage = die.attr_value(DW_AT_linkage_name)?;
@@ -85,6 +86,8 @@ where
             .get_str(offset)
             .and_then(|r| r.to_string().map(|s| s.to_string()))
             .unwrap_or_else(|_| "".into());
+        // The alternate format leaves off the hash
+        func_name = format!("{:#}", demangle(name.as_ref()));
         let name = demangle(name.as_ref()).to_string();
         // Simplest test is whether it's in tests namespace.
         // Rust guidelines recommend all tests are in a tests module.
@@ -96,7 +99,16 @@ where
             FunctionType::Standard
         };
     }
-    Ok((low, high, func_type))
+    if func_name.is_empty() {
+        // Functions which aren't mangled such as `main` only have a name
+        if let Some(AttributeValue::DebugStrRef(offset)) = die.attr_value(DW_AT_name)? {
+            func_name = debug_str
+                .get_str(offset)
+                .and_then(|r| r.to_string().map(|s| s.to_string()))
+                .unwrap_or_default();
+        }
+    }
+    Ok((low, high, func_type, func_name))
 }
 
 /// Finds all function entry points and returns a vector
@@ -129,7 +141,9 @@ fn get_addresses_from_program<R, Offset>(
     prog: IncompleteLineNumberProgram<R>,
     entries: &[(u64, LineType)],
     project: &Path,
+    generated: &HashMap<PathBuf, PathBuf>,
     result: &mut HashMap<SourceLocation, Vec<TracerData>>,
+    columns: &mut HashMap<SourceLocation, Vec<(u64, u64)>>,
 ) -> Result<()>
 where
     R: Reader<Offset = Offset>,
@@ -138,6 +152,9 @@ where
     let (cprog, seq) = prog.sequences()?;
     for s in seq {
         let mut temp_map: HashMap<SourceLocation, TracerData> = HashMap::new();
+        // A column can have several rows in a sequence, only the first is
+        // used so a condition is counted once per evaluation
+        let mut seen_columns: HashSet<(SourceLocation, u64)> = HashSet::new();
         let mut sm = cprog.resume_from(&s);
         while let Ok(Some((header, &ln_row))) = sm.next_row() {
             // If this row isn't useful move on
@@ -165,13 +182,21 @@ where
                 };
 
                 // Source is part of project so we cover it.
-                if !is_target && path.starts_with(project) {
+                let in_project = !is_target && path.starts_with(project);
+                if in_project || !generated.is_empty() {
                     if let Some(file) = ln_row.file(header) {
                         let line = ln_row.line().unwrap();
                         let file = file.path_name();
                         if let Ok(file) = file.to_string() {
                             path.push(file.as_ref());
-                            if !path.is_file() {
+                            if !in_project {
+                                // Code generated into OUT_DIR is reported in
+                                // the package that generated it
+                                match generated.get(&path) {
+                                    Some(display) => path = display.clone(),
+                                    None => continue,
+                                }
+                            } else if !path.is_file() {
                                 // Not really a source file!
                                 continue;
                             }
@@ -183,6 +208,19 @@ where
                                 .nth(0)
                                 .unwrap_or(LineType::Unknown);
                             let loc = SourceLocation { path, line };
+                            // Columns let conditions on a line be told apart
+                            let column = match ln_row.column() {
+                                ColumnType::Column(c) => c,
+                                ColumnType::LeftEdge => 0,
+                            };
+                            if desc != LineType::TestMain
+                                && seen_columns.insert((loc.clone(), column))
+                            {
+                                columns
+                                    .entry(loc.clone())
+                                    .or_insert_with(Vec::new)
+                                    .push((column, address));
+                            }
                             if desc != LineType::TestMain && !temp_map.contains_key(&loc) {
                                 temp_map.insert(
                                     loc,
@@ -210,11 +248,71 @@ where
     Ok(())
 }
 
+/// Adds the functions with a breakpoint on their entry to the tracemap. A
+/// function spans from the line of its entry to the last line in the same file
+/// with an address inside the function
+fn add_functions(
+    result: &mut TraceMap,
+    functions: &[(u64, u64, String)],
+    columns: &HashMap<SourceLocation, Vec<(u64, u64)>>,
+) {
+    let mut locations = columns
+        .iter()
+        .flat_map(|(loc, x)| x.iter().map(move |&(_, address)| (address, loc)))
+        .collect::<Vec<_>>();
+    locations.sort_unstable_by_key(|x| x.0);
+    for &(low, high, ref name) in functions {
+        let first = match locations.binary_search_by_key(&low, |x| x.0) {
+            Ok(i) => i,
+            Err(_) => continue,
+        };
+        // Several locations can share an address so find the first
+        let first = locations[..first]
+            .iter()
+            .rposition(|x| x.0 != low)
+            .map_or(0, |i| i + 1);
+        let entry = locations[first].1;
+        let has_breakpoint = result
+            .get_child_traces(&entry.path)
+            .iter()
+            .any(|x| x.address == Some(low));
+        if !has_breakpoint {
+            continue;
+        }
+        let end = locations[first..]
+            .iter()
+            .take_while(|x| x.0 < low + high.max(1))
+            .filter(|x| x.1.path == entry.path)
+            .map(|x| x.1.line)
+            .max()
+            .unwrap_or(entry.line);
+        result.add_function(
+            &entry.path,
+            Function {
+                name: name.clone(),
+                start: entry.line,
+                end: end.max(entry.line),
+                hits: 0,
+                addresses: vec![low],
+            },
+        );
+    }
+}
+
+/// Returns true if there's a breakpoint address for the given line
+fn is_instrumented(traces: &TraceMap, file: &Path, line: u64) -> bool {
+    traces
+        .get_child_traces(file)
+        .iter()
+        .any(|x| x.line == line && x.address.is_some())
+}
+
 fn get_line_addresses(
     endian: RunTimeEndian,
     project: &Path,
     obj: &OFile,
     analysis: &HashMap<PathBuf, LineAnalysis>,
+    generated: &HashMap<PathBuf, PathBuf>,
     config: &Config,
 ) -> Result<TraceMap> {
     let mut result = TraceMap::new();
@@ -229,6 +327,10 @@ fn get_line_addresses(
     let debug_line = obj.section_data_by_name(".debug_line").unwrap_or_default();
     let debug_line = DebugLine::new(&debug_line, endian);
 
+    // Addresses of the columns of each source line, used for conditions
+    let mut columns: HashMap<SourceLocation, Vec<(u64, u64)>> = HashMap::new();
+    // Low pc, high pc offset and name of every function
+    let mut functions = vec![];
     let mut iter = debug_info.units();
     while let Ok(Some(cu)) = iter.next() {
         let addr_size = cu.address_size();
@@ -236,9 +338,16 @@ fn get_line_addresses(
             Ok(a) => a,
             _ => continue,
         };
-        let entries = get_entry_points(&cu, &abbr, &debug_strings)
+        let entry_points = get_entry_points(&cu, &abbr, &debug_strings);
+        functions.extend(
+            entry_points
+                .iter()
+                .filter(|x| x.2 != FunctionType::Generated && x.0 != 0 && !x.3.is_empty())
+                .map(|&(low, high, _, ref name)| (low, high, name.clone())),
+        );
+        let entries = entry_points
             .iter()
-            .map(|&(a, b, c)| match c {
+            .map(|&(a, b, c, _)| match c {
                 FunctionType::Test => (a, LineType::TestEntry(b)),
                 FunctionType::Standard => (a, LineType::FunctionEntry(b)),
                 FunctionType::Generated => (a, LineType::TestMain),
@@ -252,14 +361,55 @@ fn get_line_addresses(
             };
             let prog = debug_line.program(offset, addr_size, None, None)?;
             let mut temp_map: HashMap<SourceLocation, Vec<TracerData>> = HashMap::new();
-            if let Err(e) = get_addresses_from_program(prog, &entries, project, &mut temp_map) {
+            if let Err(e) = get_addresses_from_program(
+                prog,
+                &entries,
+                project,
+                generated,
+                &mut temp_map,
+                &mut columns,
+            ) {
                 debug!("Potential issue reading test addresses {}", e);
             } else {
                 // Deduplicate addresses
                 for v in temp_map.values_mut() {
                     v.dedup_by_key(|x| x.address);
                 }
-                let temp_map = temp_map
+                // Continuation lines of multi-line expressions count towards
+                // the line the expression starts on. Every evaluation of the
+                // expression runs the head line, so its own addresses are kept
+                // and a continuation address is only used if it has none.
+                // Keeping both would count one evaluation more than once
+                let mut merged: HashMap<SourceLocation, Vec<TracerData>> = HashMap::new();
+                let mut continuations: HashMap<SourceLocation, Vec<TracerData>> = HashMap::new();
+                for (mut k, mut v) in temp_map {
+                    let head = analysis.merged_line(&k.path, k.line as usize) as u64;
+                    if head == k.line {
+                        merged.entry(k).or_insert_with(Vec::new).append(&mut v);
+                    } else {
+                        k.line = head;
+                        continuations
+                            .entry(k)
+                            .or_insert_with(Vec::new)
+                            .append(&mut v);
+                    }
+                }
+                for (k, v) in continuations {
+                    let lines = merged.entry(k).or_insert_with(Vec::new);
+                    if lines.is_empty() {
+                        lines.extend(
+                            v.into_iter()
+                                .filter(|x| x.address.is_some())
+                                .min_by_key(|x| x.address),
+                        );
+                    }
+                }
+                // Only consecutive duplicates were removed from each line
+                for v in merged.values_mut() {
+                    v.sort_by_key(|x| x.address);
+                    v.dedup_by_key(|x| x.address);
+                }
+                let temp_map = merged
                     .into_iter()
                     .filter(|&(ref k, _)| {
                         !(config.ignore_tests && k.path.starts_with(project.join("tests")))
@@ -308,6 +458,69 @@ fn get_line_addresses(
             }
         }
     }
+
+    // The same rows can be read from more than one unit
+    for v in columns.values_mut() {
+        v.sort_unstable();
+        v.dedup();
+    }
+
+    add_functions(&mut result, &functions, &columns);
+
+    for (source, display) in generated {
+        if result.contains_file(display) {
+            result.set_source_path(display, source);
+        }
+    }
+
+    if config.branch_coverage {
+        for (file, ref line_analysis) in analysis.iter() {
+            if config.exclude_path(file) {
+                continue;
+            }
+            for branch in &line_analysis.branches {
+                if is_instrumented(&result, file, branch.decision)
+                    && is_instrumented(&result, file, branch.taken)
+                {
+                    result.add_branch(file, *branch);
+                }
+            }
+        }
+    }
+
+    if config.condition_coverage {
+        for (file, ref line_analysis) in analysis.iter() {
+            if config.exclude_path(file) {
+                continue;
+            }
+            for condition in &line_analysis.conditions {
+                let addresses = condition
+                    .conditions
+                    .iter()
+                    .map(|&(line, column)| {
+                        let loc = SourceLocation {
+                            path: file.to_path_buf(),
+                            line,
+                        };
+                        columns
+                            .get(&loc)
+                            .map(|x| {
+                                x.iter()
+                                    .filter(|&&(c, _)| c == column)
+                                    .map(|&(_, a)| a)
+                                    .collect::<Vec<_>>()
+                            })
+                            .unwrap_or_default()
+                    })
+                    .collect::<Vec<_>>();
+                if addresses.iter().all(|x| !x.is_empty())
+                    && is_instrumented(&result, file, condition.taken)
+                {
+                    result.add_conditions(file, condition.clone(), addresses);
+                }
+            }
+        }
+    }
     Ok(result)
 }
 
@@ -320,13 +533,20 @@ pub fn generate_tracemap(
     let file = File::open(test)?;
     let file = unsafe { MmapOptions::new().map(&file)? };
     if let Ok(obj) = OFile::parse(&*file) {
-        let analysis = get_line_analysis(project, config);
+        let generated = if config.include_generated {
+            find_generated_sources(project)
+        } else {
+            HashMap::new()
+        };
+        let analysis = get_line_analysis(project, config, &generated);
         let endian = if obj.is_little_endian() {
             RunTimeEndian::Little
         } else {
             RunTimeEndian::Big
         };
-        if let Ok(result) = get_line_addresses(endian, manifest, &obj, &analysis, config) {
+        if let Ok(result) =
+            get_line_addresses(endian, manifest, &obj, &analysis, &generated, config)
+        {
             Ok(result)
         } else {
             Err(io::Error::new(
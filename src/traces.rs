@@ -1,13 +1,14 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::cmp::{Ord, Ordering};
 use std::collections::btree_map::Iter;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fmt::{Display, Formatter, Result};
+use std::mem::{self, discriminant, Discriminant};
 use std::ops::Add;
 use std::path::{Path, PathBuf};
 
 /// Used to track the state of logical conditions
-#[derive(Debug, Clone, Copy, Default, Hash, PartialEq, Eq, PartialOrd, Serialize)]
+#[derive(Debug, Clone, Copy, Default, Hash, PartialEq, Eq, PartialOrd, Serialize, Deserialize)]
 pub struct LogicState {
     /// Whether the condition has been observed as true
     pub been_true: bool,
@@ -26,14 +27,54 @@ impl<'a> Add for &'a LogicState {
     }
 }
 
+/// Lines in a source file describing a branch. Whether a branch has been taken
+/// is decided by the hits on the first line of its body and whether it has
+/// been skipped by the decision line being hit more often than the body.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BranchLines {
+    /// Line the branch coverage is reported on
+    pub line: u64,
+    /// Line executed every time the decision is evaluated
+    pub decision: u64,
+    /// First line executed when the branch is taken
+    pub taken: u64,
+}
+
+/// Short-circuiting boolean operator joining the conditions of a decision
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum Operator {
+    /// Conditions joined with `&&`
+    And,
+    /// Conditions joined with `||`
+    Or,
+}
+
+/// Conditions of a decision guarding a branch. Each condition is only
+/// evaluated if the ones before it didn't decide the outcome, so the number of
+/// times each is evaluated along with the hits on the branch body gives how
+/// often each condition was true or false.
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct ConditionLines {
+    /// Line the condition coverage is reported on
+    pub line: u64,
+    /// First line executed when the decision is true
+    pub taken: u64,
+    /// Operator joining the conditions
+    pub operator: Operator,
+    /// Line and column of the start of each condition in evaluation order.
+    /// Columns start from 1 matching the DWARF line tables
+    pub conditions: Vec<(u64, u64)>,
+}
+
 /// Shows what type of coverage data is being collected by a given trace
-#[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Serialize)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Serialize, Deserialize)]
 pub enum CoverageStat {
     /// Line coverage data (whether line has been hit)
     Line(u64),
     /// Branch coverage data (whether branch has been true and false
     Branch(LogicState),
-    /// Condition coverage data (each boolean subcondition true and false)
+    /// Condition coverage data (whether each boolean subcondition has been
+    /// true and false, not whether each independently changed the outcome)
     Condition(Vec<LogicState>),
 }
 
@@ -46,6 +87,17 @@ impl Add for CoverageStat {
             (CoverageStat::Branch(ref l), CoverageStat::Branch(ref r)) => {
                 CoverageStat::Branch(l + r)
             }
+            (CoverageStat::Condition(ref l), CoverageStat::Condition(ref r)) => {
+                let len = l.len().max(r.len());
+                let merged = (0..len)
+                    .map(|i| match (l.get(i), r.get(i)) {
+                        (Some(a), Some(b)) => a + b,
+                        (Some(a), None) | (None, Some(a)) => *a,
+                        (None, None) => LogicState::default(),
+                    })
+                    .collect();
+                CoverageStat::Condition(merged)
+            }
             t => t.0,
         }
     }
@@ -55,12 +107,22 @@ impl Display for CoverageStat {
     fn fmt(&self, f: &mut Formatter) -> Result {
         match *self {
             CoverageStat::Line(x) => write!(f, "hits: {}", x),
+            CoverageStat::Branch(ref x) => {
+                write!(f, "taken: {}, not taken: {}", x.been_true, x.been_false)
+            }
+            CoverageStat::Condition(ref x) => {
+                let states = x
+                    .iter()
+                    .map(|c| format!("(true: {}, false: {})", c.been_true, c.been_false))
+                    .collect::<Vec<_>>();
+                write!(f, "conditions: {}", states.join(", "))
+            }
             _ => write!(f, ""),
         }
     }
 }
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Serialize)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Serialize, Deserialize)]
 pub struct Trace {
     /// Line the trace is on in the file
     pub line: u64,
@@ -93,6 +155,48 @@ impl Ord for Trace {
     }
 }
 
+/// A function in the program along with how often it was entered
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Function {
+    /// Demangled name of the function without its hash
+    pub name: String,
+    /// First line of the function
+    pub start: u64,
+    /// Last line of the function
+    pub end: u64,
+    /// Number of times the function was entered
+    pub hits: u64,
+    /// Entry addresses of every instance of the function in the test binary.
+    /// These are specific to a test binary so aren't merged or saved
+    #[serde(skip)]
+    pub addresses: Vec<u64>,
+}
+
+/// Gets the line coverage traces from the provided traces, leaving out the
+/// branches and conditions
+pub fn line_traces<'a>(traces: &[&'a Trace]) -> Vec<&'a Trace> {
+    traces
+        .iter()
+        .filter(|x| match x.stats {
+            CoverageStat::Line(_) => true,
+            _ => false,
+        })
+        .cloned()
+        .collect()
+}
+
+/// Gets the branch and condition traces from the provided traces
+pub fn logic_traces<'a>(traces: &[&'a Trace]) -> Vec<&'a Trace> {
+    traces
+        .iter()
+        .filter(|x| match x.stats {
+            CoverageStat::Branch(_) | CoverageStat::Condition(_) => true,
+            _ => false,
+        })
+        .cloned()
+        .collect()
+}
+
 /// Amount of data coverable in the provided slice traces
 pub fn amount_coverable(traces: &[&Trace]) -> usize {
     let mut result = 0usize;
@@ -121,16 +225,93 @@ pub fn amount_covered(traces: &[&Trace]) -> usize {
     result
 }
 
+/// Hits on the given line, taken from its trace with the lowest address. Lines
+/// such as loop headers and match scrutinees can have several addresses and
+/// summing them would count one evaluation of the line more than once
+fn line_hits(traces: &[Trace], line: u64) -> u64 {
+    traces
+        .iter()
+        .filter(|x| x.line == line)
+        .filter_map(|x| match x.stats {
+            CoverageStat::Line(hits) => Some((x.address, hits)),
+            _ => None,
+        })
+        .min_by_key(|&(address, _)| address.unwrap_or(u64::max_value()))
+        .map_or(0, |(_, hits)| hits)
+}
+
 pub fn coverage_percentage(traces: &[&Trace]) -> f64 {
     (amount_covered(traces) as f64) / (amount_coverable(traces) as f64)
 }
 
+/// Works out whether each condition has been true and false from how often
+/// each was evaluated and how often the decision was true. With `&&` a
+/// condition is true when the next one is evaluated and with `||` false.
+fn get_condition_states(operator: Operator, evals: &[u64], taken: u64) -> Vec<LogicState> {
+    let n = evals.len();
+    (0..n)
+        .map(|i| {
+            let e = evals[i];
+            match operator {
+                Operator::And => {
+                    let next = if i + 1 < n { evals[i + 1] } else { taken };
+                    LogicState {
+                        been_true: next > 0,
+                        been_false: e > next,
+                    }
+                }
+                Operator::Or if i + 1 < n => {
+                    let next = evals[i + 1];
+                    LogicState {
+                        been_true: e > next,
+                        been_false: next > 0,
+                    }
+                }
+                Operator::Or => {
+                    // Decision true from earlier conditions is the difference
+                    // between the first and last condition evaluations
+                    let last_true = taken.saturating_sub(evals[0].saturating_sub(e));
+                    LogicState {
+                        been_true: last_true > 0,
+                        been_false: e > last_true,
+                    }
+                }
+            }
+        })
+        .collect()
+}
+
 /// Stores all the program traces mapped to files and provides an interface to
 /// add, query and change traces.
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct TraceMap {
     /// Traces in the program mapped to the given file
     traces: BTreeMap<PathBuf, Vec<Trace>>,
+    /// Branches in the program mapped to the given file
+    branches: BTreeMap<PathBuf, Vec<BranchLines>>,
+    /// Conditions in the program mapped to the given file along with the
+    /// addresses of each condition. Addresses are specific to a test binary
+    /// so these aren't merged or saved
+    #[serde(skip)]
+    conditions: BTreeMap<PathBuf, Vec<(ConditionLines, Vec<Vec<u64>>)>>,
+    /// Addresses of conditions mapped to the file and index of the decision
+    /// they're part of
+    #[serde(skip)]
+    condition_index: HashMap<u64, (PathBuf, usize)>,
+    /// Number of times each condition address has been hit
+    #[serde(skip)]
+    condition_hits: HashMap<u64, u64>,
+    /// Names of the tests which hit each line mapped to the given file. Only
+    /// filled in when tests are run one at a time
+    #[serde(default)]
+    tests: BTreeMap<PathBuf, BTreeMap<u64, BTreeSet<String>>>,
+    /// Functions in the program mapped to the given file
+    #[serde(default)]
+    functions: BTreeMap<PathBuf, Vec<Function>>,
+    /// Files reported under a different path to their source, such as
+    /// generated code, mapped to the path of their source
+    #[serde(default)]
+    sources: BTreeMap<PathBuf, PathBuf>,
 }
 
 impl TraceMap {
@@ -138,6 +319,13 @@ impl TraceMap {
     pub fn new() -> TraceMap {
         TraceMap {
             traces: BTreeMap::new(),
+            branches: BTreeMap::new(),
+            conditions: BTreeMap::new(),
+            condition_index: HashMap::new(),
+            condition_hits: HashMap::new(),
+            tests: BTreeMap::new(),
+            functions: BTreeMap::new(),
+            sources: BTreeMap::new(),
         }
     }
 
@@ -162,10 +350,11 @@ impl TraceMap {
                 let existing = self.traces.get_mut(k).unwrap();
                 for ref v in values.iter() {
                     let mut added = false;
-                    if let Some(ref mut t) = existing
-                        .iter_mut()
-                        .find(|ref x| x.line == v.line && x.address == v.address)
-                    {
+                    if let Some(ref mut t) = existing.iter_mut().find(|ref x| {
+                        x.line == v.line
+                            && x.address == v.address
+                            && discriminant(&x.stats) == discriminant(&v.stats)
+                    }) {
                         t.stats = t.stats.clone() + v.stats.clone();
                         added = true;
                     }
@@ -176,6 +365,82 @@ impl TraceMap {
                 }
             }
         }
+        for (k, values) in &other.branches {
+            let existing = self
+                .branches
+                .entry(k.to_path_buf())
+                .or_insert_with(Vec::new);
+            for v in values {
+                if !existing.contains(v) {
+                    existing.push(*v);
+                }
+            }
+        }
+        for (k, lines) in &other.tests {
+            let existing = self
+                .tests
+                .entry(k.to_path_buf())
+                .or_insert_with(BTreeMap::new);
+            for (line, tests) in lines {
+                existing
+                    .entry(*line)
+                    .or_insert_with(BTreeSet::new)
+                    .extend(tests.iter().cloned());
+            }
+        }
+        for (k, v) in &other.sources {
+            self.sources.insert(k.to_path_buf(), v.to_path_buf());
+        }
+        for (k, functions) in &other.functions {
+            for f in functions {
+                let mut f = f.clone();
+                // Hits from the other map are already resolved
+                f.addresses.clear();
+                self.add_function(k, f);
+            }
+        }
+    }
+
+    /// Replaces the `from` prefix of file paths with `to`. Used to line up
+    /// results gathered in different checkouts of a project, files which end
+    /// up with the same path are merged
+    pub fn replace_prefix(&mut self, from: &Path, to: &Path) {
+        let rename = |p: &Path| match p.strip_prefix(from) {
+            Ok(rest) => to.join(rest),
+            Err(_) => p.to_path_buf(),
+        };
+        let old = mem::replace(self, TraceMap::new());
+        for (k, v) in old.traces {
+            let mut renamed = TraceMap::new();
+            renamed.traces.insert(rename(&k), v);
+            self.merge(&renamed);
+        }
+        for (k, v) in old.branches {
+            let mut renamed = TraceMap::new();
+            renamed.branches.insert(rename(&k), v);
+            self.merge(&renamed);
+        }
+        for (k, v) in old.tests {
+            let mut renamed = TraceMap::new();
+            renamed.tests.insert(rename(&k), v);
+            self.merge(&renamed);
+        }
+        for (k, v) in old.sources {
+            self.sources.insert(rename(&k), rename(&v));
+        }
+        for (k, v) in old.functions {
+            for f in v {
+                self.add_function(&rename(&k), f);
+            }
+        }
+        for (k, mut v) in old.conditions {
+            self.conditions
+                .entry(rename(&k))
+                .or_insert_with(Vec::new)
+                .append(&mut v);
+        }
+        self.index_conditions();
+        self.condition_hits = old.condition_hits;
     }
 
     /// This will collapse duplicate Traces into a single trace. Warning this
@@ -184,15 +449,18 @@ impl TraceMap {
     /// TODO possibly not the cleanest solution
     pub fn dedup(&mut self) {
         for values in self.traces.values_mut() {
-            // Map of lines and stats, merge duplicated stats here
-            let mut lines: HashMap<u64, CoverageStat> = HashMap::new();
+            // Map of lines and stats, merge duplicated stats here. Different
+            // kinds of stats can exist on the same line so keep them apart
+            let mut lines: HashMap<(u64, Discriminant<CoverageStat>), CoverageStat> =
+                HashMap::new();
             // Duplicated traces need cleaning up. Maintain a list of them!
-            let mut dirty: Vec<u64> = Vec::new();
+            let mut dirty: Vec<(u64, Discriminant<CoverageStat>)> = Vec::new();
             for v in values.iter() {
+                let key = (v.line, discriminant(&v.stats));
                 lines
-                    .entry(v.line)
+                    .entry(key)
                     .and_modify(|e| {
-                        dirty.push(v.line);
+                        dirty.push(key);
                         *e = e.clone() + v.stats.clone();
                     })
                     .or_insert_with(|| v.stats.clone());
@@ -200,7 +468,7 @@ impl TraceMap {
             for d in &dirty {
                 let mut first = true;
                 values.retain(|x| {
-                    let res = x.line != *d;
+                    let res = (x.line, discriminant(&x.stats)) != *d;
                     if !res {
                         if first {
                             first = false;
@@ -213,7 +481,10 @@ impl TraceMap {
                     }
                 });
                 if let Some(new_stat) = lines.remove(&d) {
-                    if let Some(ref mut t) = values.iter_mut().find(|x| x.line == *d) {
+                    if let Some(ref mut t) = values
+                        .iter_mut()
+                        .find(|x| (x.line, discriminant(&x.stats)) == *d)
+                    {
                         t.stats = new_stat;
                     }
                 }
@@ -233,6 +504,258 @@ impl TraceMap {
         }
     }
 
+    /// Add a branch to the tracemap for the given file. Branches sharing a
+    /// line are reported in the same trace
+    pub fn add_branch(&mut self, file: &Path, branch: BranchLines) {
+        let has_trace = self.traces.get(file).map_or(false, |traces| {
+            traces.iter().any(|x| match x.stats {
+                CoverageStat::Branch(_) => x.line == branch.line,
+                _ => false,
+            })
+        });
+        if !has_trace {
+            self.add_trace(
+                file,
+                Trace {
+                    line: branch.line,
+                    address: None,
+                    length: 0,
+                    stats: CoverageStat::Branch(LogicState::default()),
+                },
+            );
+        }
+        let branches = self
+            .branches
+            .entry(file.to_path_buf())
+            .or_insert_with(Vec::new);
+        if !branches.contains(&branch) {
+            branches.push(branch);
+        }
+    }
+
+    /// Gets the addresses of the lines used to resolve branch outcomes. To
+    /// compare hits on these lines their breakpoints have to stay enabled
+    pub fn branch_addresses(&self) -> HashSet<u64> {
+        let mut result = HashSet::new();
+        for (file, branches) in &self.branches {
+            if let Some(traces) = self.traces.get(file) {
+                for b in branches {
+                    result.extend(
+                        traces
+                            .iter()
+                            .filter(|x| x.line == b.decision || x.line == b.taken)
+                            .filter_map(|x| x.address),
+                    );
+                }
+            }
+        }
+        result
+    }
+
+    /// Add the conditions of a decision to the tracemap for the given file,
+    /// `addresses` holds the addresses for each condition in the test binary
+    pub fn add_conditions(
+        &mut self,
+        file: &Path,
+        conditions: ConditionLines,
+        addresses: Vec<Vec<u64>>,
+    ) {
+        let has_trace = self.traces.get(file).map_or(false, |traces| {
+            traces.iter().any(|x| match x.stats {
+                CoverageStat::Condition(_) => x.line == conditions.line,
+                _ => false,
+            })
+        });
+        if !has_trace {
+            self.add_trace(
+                file,
+                Trace {
+                    line: conditions.line,
+                    address: None,
+                    length: 0,
+                    stats: CoverageStat::Condition(vec![
+                        LogicState::default();
+                        conditions.conditions.len()
+                    ]),
+                },
+            );
+            let decisions = self
+                .conditions
+                .entry(file.to_path_buf())
+                .or_insert_with(Vec::new);
+            for a in addresses.iter().flat_map(|x| x.iter()) {
+                self.condition_index
+                    .insert(*a, (file.to_path_buf(), decisions.len()));
+            }
+            decisions.push((conditions, addresses));
+        }
+    }
+
+    /// Rebuilds the index of condition addresses after the conditions change
+    fn index_conditions(&mut self) {
+        self.condition_index.clear();
+        for (file, decisions) in &self.conditions {
+            for (i, &(_, ref addresses)) in decisions.iter().enumerate() {
+                for a in addresses.iter().flat_map(|x| x.iter()) {
+                    self.condition_index.insert(*a, (file.to_path_buf(), i));
+                }
+            }
+        }
+    }
+
+    /// Add a function to the tracemap for the given file. Instances of a
+    /// function with the same name and first line, such as the
+    /// monomorphisations of a generic function, are combined
+    pub fn add_function(&mut self, file: &Path, function: Function) {
+        let functions = self
+            .functions
+            .entry(file.to_path_buf())
+            .or_insert_with(Vec::new);
+        if let Some(f) = functions
+            .iter_mut()
+            .find(|x| x.name == function.name && x.start == function.start)
+        {
+            f.end = f.end.max(function.end);
+            f.hits += function.hits;
+            f.addresses.extend(function.addresses);
+        } else {
+            functions.push(function);
+            functions.sort_unstable_by_key(|x| x.start);
+        }
+    }
+
+    /// Sets the path to read the source of a file from when it differs from
+    /// the path it's reported under
+    pub fn set_source_path(&mut self, file: &Path, source: &Path) {
+        self.sources
+            .insert(file.to_path_buf(), source.to_path_buf());
+    }
+
+    /// Gets the path to read the source of the given file from
+    pub fn source_path<'a>(&'a self, file: &'a Path) -> &'a Path {
+        self.sources.get(file).map_or(file, |x| x.as_path())
+    }
+
+    /// Gets the functions in the given file
+    pub fn functions_in_file(&self, file: &Path) -> &[Function] {
+        self.functions.get(file).map_or(&[][..], |x| x.as_slice())
+    }
+
+    /// Gets all functions
+    pub fn all_functions(&self) -> Vec<&Function> {
+        self.functions.values().flat_map(|x| x.iter()).collect()
+    }
+
+    /// Number of functions entered at least once
+    pub fn total_functions_covered(&self) -> usize {
+        self.all_functions().iter().filter(|x| x.hits > 0).count()
+    }
+
+    /// Records the given test as hitting every line with hits
+    pub fn add_test_hits(&mut self, test: &str) {
+        for (k, traces) in &self.traces {
+            for t in traces {
+                if let CoverageStat::Line(hits) = t.stats {
+                    if hits > 0 {
+                        self.tests
+                            .entry(k.to_path_buf())
+                            .or_insert_with(BTreeMap::new)
+                            .entry(t.line)
+                            .or_insert_with(BTreeSet::new)
+                            .insert(test.to_string());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Gets the names of the tests which hit each line in the given file
+    pub fn tests_in_file(&self, file: &Path) -> Option<&BTreeMap<u64, BTreeSet<String>>> {
+        self.tests.get(file)
+    }
+
+    /// Gets the addresses of conditions and the lines they guard. These need
+    /// breakpoints which stay enabled to count how often they're evaluated
+    pub fn condition_addresses(&self) -> HashSet<u64> {
+        let mut result = HashSet::new();
+        for (file, conditions) in &self.conditions {
+            for &(ref cond, ref addresses) in conditions {
+                result.extend(addresses.iter().flat_map(|x| x.iter().cloned()));
+                if let Some(traces) = self.traces.get(file) {
+                    result.extend(
+                        traces
+                            .iter()
+                            .filter(|x| x.line == cond.taken)
+                            .filter_map(|x| x.address),
+                    );
+                }
+            }
+        }
+        result
+    }
+
+    /// Records hits on an address if it's the address of a condition
+    pub fn add_condition_hit(&mut self, address: u64, hits: u64) {
+        if hits > 0 && self.condition_index.contains_key(&address) {
+            *self.condition_hits.entry(address).or_insert(0) += hits;
+        }
+    }
+
+    /// Updates the branch and condition traces from the hits collected in the
+    /// run
+    pub fn resolve_logic(&mut self) {
+        for (file, branches) in &self.branches {
+            if let Some(traces) = self.traces.get_mut(file) {
+                for b in branches {
+                    let taken = line_hits(traces, b.taken);
+                    let state = LogicState {
+                        been_true: taken > 0,
+                        been_false: line_hits(traces, b.decision) > taken,
+                    };
+                    for t in traces.iter_mut().filter(|x| x.line == b.line) {
+                        if let CoverageStat::Branch(ref mut s) = t.stats {
+                            *s = &*s + &state;
+                        }
+                    }
+                }
+            }
+        }
+        let hits = &self.condition_hits;
+        for (file, conditions) in &self.conditions {
+            if let Some(traces) = self.traces.get_mut(file) {
+                for &(ref cond, ref addresses) in conditions {
+                    let evals = addresses
+                        .iter()
+                        .map(|x| x.iter().map(|a| hits.get(a).cloned().unwrap_or(0)).sum())
+                        .collect::<Vec<u64>>();
+                    let taken = line_hits(traces, cond.taken);
+                    let states = get_condition_states(cond.operator, &evals, taken);
+                    for t in traces.iter_mut().filter(|x| x.line == cond.line) {
+                        if let CoverageStat::Condition(_) = t.stats {
+                            t.stats = t.stats.clone() + CoverageStat::Condition(states.clone());
+                        }
+                    }
+                }
+            }
+        }
+        for (file, functions) in self.functions.iter_mut() {
+            if let Some(traces) = self.traces.get(file) {
+                for f in functions.iter_mut() {
+                    let hits = traces
+                        .iter()
+                        .filter(|x| x.address.map_or(false, |a| f.addresses.contains(&a)))
+                        .map(|x| match x.stats {
+                            CoverageStat::Line(hits) => hits,
+                            _ => 0,
+                        })
+                        .sum::<u64>();
+                    f.hits += hits;
+                    f.addresses.clear();
+                }
+            }
+        }
+    }
+
     /// Gets an immutable reference to a trace from an address. Returns None if
     /// there is no trace at that address
     pub fn get_trace(&self, address: u64) -> Option<&Trace> {
@@ -253,6 +776,11 @@ impl TraceMap {
         None
     }
 
+    /// Returns true if there are traces for the given file
+    pub fn contains_file(&self, file: &Path) -> bool {
+        self.traces.contains_key(file)
+    }
+
     /// Returns true if the location described by file and line number is present
     /// in the tracemap
     pub fn contains_location(&self, file: &Path, line: u64) -> bool {
@@ -302,30 +830,41 @@ impl TraceMap {
         self.traces.keys().collect()
     }
 
+    /// Number of coverable lines in the path
     pub fn coverable_in_path(&self, path: &Path) -> usize {
-        amount_coverable(self.get_child_traces(path).as_slice())
+        amount_coverable(&line_traces(&self.get_child_traces(path)))
     }
 
+    /// Number of covered lines in the path
     pub fn covered_in_path(&self, path: &Path) -> usize {
-        amount_covered(self.get_child_traces(path).as_slice())
+        amount_covered(&line_traces(&self.get_child_traces(path)))
     }
 
-    /// Give the total amount of coverable points in the code. This will vary
-    /// based on the statistics available for line coverage it will be total
-    /// lines whereas for condition or decision it will count the number of
-    /// conditions available
+    /// Give the total amount of coverable lines in the code. Branches and
+    /// conditions are counted separately by `total_logic_coverable`
     pub fn total_coverable(&self) -> usize {
-        amount_coverable(self.all_traces().as_slice())
+        amount_coverable(&line_traces(&self.all_traces()))
     }
 
-    /// From all the coverable data return the amount covered
+    /// From all the coverable lines return the amount covered
     pub fn total_covered(&self) -> usize {
-        amount_covered(self.all_traces().as_slice())
+        amount_covered(&line_traces(&self.all_traces()))
+    }
+
+    /// Total number of branch and condition outcomes, each branch or
+    /// condition can be true and false
+    pub fn total_logic_coverable(&self) -> usize {
+        amount_coverable(&logic_traces(&self.all_traces()))
+    }
+
+    /// Number of branch and condition outcomes which have been seen
+    pub fn total_logic_covered(&self) -> usize {
+        amount_covered(&logic_traces(&self.all_traces()))
     }
 
-    /// Returns coverage percentage ranging from 0.0-1.0
+    /// Returns line coverage percentage ranging from 0.0-1.0
     pub fn coverage_percentage(&self) -> f64 {
-        coverage_percentage(self.all_traces().as_slice())
+        coverage_percentage(&line_traces(&self.all_traces()))
     }
 }
 
@@ -372,6 +911,14 @@ mod tests {
         assert_eq!(&t + &n, t);
         assert_eq!(&n + &f, f);
         assert_eq!(&n + &n, n);
+
+        let c1 = CoverageStat::Condition(vec![t, f]);
+        let c2 = CoverageStat::Condition(vec![f, n, t]);
+        assert_eq!(
+            c1.clone() + c2.clone(),
+            CoverageStat::Condition(vec![tf, f, t])
+        );
+        assert_eq!(c2 + c1, CoverageStat::Condition(vec![tf, f, t]));
     }
 
     #[test]
@@ -482,4 +1029,432 @@ mod tests {
             })
         );
     }
+
+    #[test]
+    fn dedup_keeps_stat_kinds() {
+        let mut t1 = TraceMap::new();
+        t1.add_trace(
+            Path::new("file.rs"),
+            Trace {
+                line: 1,
+                address: Some(1),
+                length: 1,
+                stats: CoverageStat::Line(1),
+            },
+        );
+        t1.add_trace(
+            Path::new("file.rs"),
+            Trace {
+                line: 1,
+                address: Some(2),
+                length: 1,
+                stats: CoverageStat::Line(2),
+            },
+        );
+        t1.add_branch(
+            Path::new("file.rs"),
+            BranchLines {
+                line: 1,
+                decision: 1,
+                taken: 2,
+            },
+        );
+        t1.dedup();
+        let all = t1.all_traces();
+        assert_eq!(all.len(), 2);
+        assert!(all.iter().any(|x| x.stats == CoverageStat::Line(3)));
+        assert!(all
+            .iter()
+            .any(|x| x.stats == CoverageStat::Branch(LogicState::default())));
+    }
+
+    #[test]
+    fn resolve_branches() {
+        let mut t1 = TraceMap::new();
+        let file = Path::new("file.rs");
+        for &(line, hits) in &[(1, 3), (2, 3), (3, 3), (4, 0), (5, 2), (6, 1)] {
+            t1.add_trace(
+                file,
+                Trace {
+                    line,
+                    address: Some(line),
+                    length: 1,
+                    stats: CoverageStat::Line(hits),
+                },
+            );
+        }
+        // Always taken
+        t1.add_branch(
+            file,
+            BranchLines {
+                line: 1,
+                decision: 1,
+                taken: 2,
+            },
+        );
+        // Never taken
+        t1.add_branch(
+            file,
+            BranchLines {
+                line: 4,
+                decision: 3,
+                taken: 4,
+            },
+        );
+        // Taken some of the time
+        t1.add_branch(
+            file,
+            BranchLines {
+                line: 5,
+                decision: 5,
+                taken: 6,
+            },
+        );
+        let mut addresses = t1.branch_addresses().into_iter().collect::<Vec<_>>();
+        addresses.sort();
+        assert_eq!(addresses, vec![1, 2, 3, 4, 5, 6]);
+
+        t1.resolve_logic();
+        let branch_at = |line: u64| {
+            t1.get_child_traces(file)
+                .iter()
+                .filter(|x| x.line == line)
+                .filter_map(|x| match x.stats {
+                    CoverageStat::Branch(s) => Some(s),
+                    _ => None,
+                })
+                .nth(0)
+        };
+        assert_eq!(
+            branch_at(1),
+            Some(LogicState {
+                been_true: true,
+                been_false: false,
+            })
+        );
+        assert_eq!(
+            branch_at(4),
+            Some(LogicState {
+                been_true: false,
+                been_false: true,
+            })
+        );
+        assert_eq!(
+            branch_at(5),
+            Some(LogicState {
+                been_true: true,
+                been_false: true,
+            })
+        );
+    }
+
+    #[test]
+    fn multi_address_decision() {
+        let mut t1 = TraceMap::new();
+        let file = Path::new("file.rs");
+        // The decision line is hit at two addresses each time it's evaluated
+        for &(line, address, hits) in &[(1, 10, 2), (1, 30, 2), (2, 20, 2)] {
+            t1.add_trace(
+                file,
+                Trace {
+                    line,
+                    address: Some(address),
+                    length: 1,
+                    stats: CoverageStat::Line(hits),
+                },
+            );
+        }
+        t1.add_branch(
+            file,
+            BranchLines {
+                line: 1,
+                decision: 1,
+                taken: 2,
+            },
+        );
+        t1.resolve_logic();
+        let states = t1
+            .get_child_traces(file)
+            .iter()
+            .filter_map(|x| match x.stats {
+                CoverageStat::Branch(s) => Some(s),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            states,
+            vec![LogicState {
+                been_true: true,
+                been_false: false,
+            }]
+        );
+    }
+
+    #[test]
+    fn line_metrics_exclude_logic() {
+        let mut t1 = TraceMap::new();
+        let file = Path::new("/project/file.rs");
+        let half = LogicState {
+            been_true: true,
+            been_false: false,
+        };
+        for (line, stats) in vec![
+            (1, CoverageStat::Line(1)),
+            (2, CoverageStat::Line(0)),
+            (1, CoverageStat::Branch(half)),
+            (3, CoverageStat::Condition(vec![half, half])),
+        ] {
+            t1.add_trace(
+                file,
+                Trace {
+                    line,
+                    address: None,
+                    length: 0,
+                    stats,
+                },
+            );
+        }
+        assert_eq!(t1.total_coverable(), 2);
+        assert_eq!(t1.total_covered(), 1);
+        assert_eq!(t1.coverable_in_path(Path::new("/project")), 2);
+        assert_eq!(t1.covered_in_path(Path::new("/project")), 1);
+        assert!((t1.coverage_percentage() - 0.5).abs() < 1e-9);
+        assert_eq!(t1.total_logic_coverable(), 6);
+        assert_eq!(t1.total_logic_covered(), 3);
+    }
+
+    #[test]
+    fn merged_decision() {
+        let mut t1 = TraceMap::new();
+        let file = Path::new("file.rs");
+        // A decision spread over lines 1 and 2 whose only code is on the
+        // continuation line, which is reported on line 1 at its address
+        for &(line, address, hits) in &[(1, 30, 4), (3, 40, 1)] {
+            t1.add_trace(
+                file,
+                Trace {
+                    line,
+                    address: Some(address),
+                    length: 1,
+                    stats: CoverageStat::Line(hits),
+                },
+            );
+        }
+        t1.add_branch(
+            file,
+            BranchLines {
+                line: 1,
+                decision: 1,
+                taken: 3,
+            },
+        );
+        t1.resolve_logic();
+        let states = t1
+            .get_child_traces(file)
+            .iter()
+            .filter_map(|x| match x.stats {
+                CoverageStat::Branch(s) => Some(s),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            states,
+            vec![LogicState {
+                been_true: true,
+                been_false: true,
+            }]
+        );
+    }
+
+    #[test]
+    fn condition_states() {
+        let tf = LogicState {
+            been_true: true,
+            been_false: true,
+        };
+        let t = LogicState {
+            been_true: true,
+            been_false: false,
+        };
+        let f = LogicState {
+            been_true: false,
+            been_false: true,
+        };
+        // a && b evaluated 4 times, b evaluated twice and decision true once
+        assert_eq!(
+            get_condition_states(Operator::And, &[4, 2], 1),
+            vec![tf, tf]
+        );
+        // a && b never getting past a
+        assert_eq!(
+            get_condition_states(Operator::And, &[3, 0], 0),
+            vec![f, LogicState::default()]
+        );
+        // a || b evaluated 4 times, b evaluated once and true
+        assert_eq!(get_condition_states(Operator::Or, &[4, 1], 4), vec![tf, t]);
+        // a || b with b false once
+        assert_eq!(get_condition_states(Operator::Or, &[4, 2], 3), vec![tf, tf]);
+    }
+
+    #[test]
+    fn condition_hits() {
+        let mut t1 = TraceMap::new();
+        let file = Path::new("/ci/file.rs");
+        t1.add_trace(
+            file,
+            Trace {
+                line: 2,
+                address: Some(20),
+                length: 1,
+                stats: CoverageStat::Line(1),
+            },
+        );
+        t1.add_conditions(
+            file,
+            ConditionLines {
+                line: 1,
+                taken: 2,
+                operator: Operator::And,
+                conditions: vec![(1, 4), (1, 9)],
+            },
+            vec![vec![10], vec![11]],
+        );
+        t1.replace_prefix(Path::new("/ci"), Path::new("/dev"));
+        for &(address, hits) in &[(10, 4), (11, 2), (20, 1), (99, 1), (12, 0)] {
+            t1.add_condition_hit(address, hits);
+        }
+        t1.resolve_logic();
+        let tf = LogicState {
+            been_true: true,
+            been_false: true,
+        };
+        let states = t1
+            .get_child_traces(Path::new("/dev/file.rs"))
+            .iter()
+            .filter_map(|x| match x.stats {
+                CoverageStat::Condition(ref s) => Some(s.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(states, vec![vec![tf, tf]]);
+        assert_eq!(t1.condition_hits.get(&20), None);
+        assert_eq!(t1.condition_hits.get(&99), None);
+    }
+
+    #[test]
+    fn serialize_roundtrip() {
+        let mut t1 = TraceMap::new();
+        let file = Path::new("file.rs");
+        t1.add_trace(
+            file,
+            Trace {
+                line: 1,
+                address: Some(10),
+                length: 1,
+                stats: CoverageStat::Line(3),
+            },
+        );
+        t1.add_branch(
+            file,
+            BranchLines {
+                line: 2,
+                decision: 1,
+                taken: 2,
+            },
+        );
+        let json = serde_json::to_string(&t1).unwrap();
+        let t2: TraceMap = serde_json::from_str(&json).unwrap();
+        assert_eq!(t1.all_traces(), t2.all_traces());
+        assert_eq!(t1.branch_addresses(), t2.branch_addresses());
+    }
+
+    #[test]
+    fn prefix_replacement() {
+        let mut t1 = TraceMap::new();
+        let trace = |line, hits| Trace {
+            line,
+            address: Some(line),
+            length: 1,
+            stats: CoverageStat::Line(hits),
+        };
+        t1.add_trace(Path::new("/ci/build/src/lib.rs"), trace(1, 0));
+        t1.add_trace(Path::new("/home/dev/project/src/lib.rs"), trace(1, 2));
+        t1.add_trace(Path::new("/other/src/main.rs"), trace(2, 1));
+
+        t1.replace_prefix(Path::new("/ci/build"), Path::new("/home/dev/project"));
+
+        assert_eq!(
+            t1.files(),
+            vec![
+                Path::new("/home/dev/project/src/lib.rs"),
+                Path::new("/other/src/main.rs")
+            ]
+        );
+        assert_eq!(
+            t1.get_child_traces(Path::new("/home/dev/project")),
+            vec![&trace(1, 2)]
+        );
+    }
+
+    #[test]
+    fn test_attribution() {
+        let file = Path::new("file.rs");
+        let trace = |line, hits| Trace {
+            line,
+            address: Some(line),
+            length: 1,
+            stats: CoverageStat::Line(hits),
+        };
+        let mut t1 = TraceMap::new();
+        t1.add_trace(file, trace(1, 1));
+        t1.add_trace(file, trace(2, 0));
+        t1.add_test_hits("a");
+        let mut t2 = TraceMap::new();
+        t2.add_trace(file, trace(1, 2));
+        t2.add_trace(file, trace(2, 1));
+        t2.add_test_hits("b");
+
+        t1.merge(&t2);
+        let tests = t1.tests_in_file(file).unwrap();
+        let names = |line| tests[&line].iter().map(|x| x.as_str()).collect::<Vec<_>>();
+        assert_eq!(names(1), vec!["a", "b"]);
+        assert_eq!(names(2), vec!["b"]);
+    }
+
+    #[test]
+    fn function_hits() {
+        let file = Path::new("file.rs");
+        let trace = |line, address, hits| Trace {
+            line,
+            address: Some(address),
+            length: 1,
+            stats: CoverageStat::Line(hits),
+        };
+        let function = |addresses| Function {
+            name: "foo::bar".to_string(),
+            start: 1,
+            end: 3,
+            hits: 0,
+            addresses,
+        };
+        let mut t1 = TraceMap::new();
+        t1.add_trace(file, trace(1, 10, 2));
+        t1.add_trace(file, trace(1, 20, 1));
+        t1.add_trace(file, trace(2, 11, 3));
+        // Two instances of a generic function
+        t1.add_function(file, function(vec![10]));
+        t1.add_function(file, function(vec![20]));
+        t1.resolve_logic();
+        assert_eq!(t1.functions_in_file(file).len(), 1);
+        assert_eq!(t1.functions_in_file(file)[0].hits, 3);
+
+        let mut t2 = TraceMap::new();
+        t2.add_trace(file, trace(1, 10, 0));
+        t2.add_function(file, function(vec![10]));
+        t2.resolve_logic();
+        t1.merge(&t2);
+        assert_eq!(t1.functions_in_file(file)[0].hits, 3);
+        assert_eq!(t1.total_functions_covered(), 1);
+        assert_eq!(t2.total_functions_covered(), 0);
+    }
 }
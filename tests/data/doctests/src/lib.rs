@@ -0,0 +1,8 @@
+/// Only called by the doctest
+///
+/// ```
+/// assert_eq!(doctests::square(3), 9);
+/// ```
+pub fn square(x: u64) -> u64 {
+    x * x
+}
@@ -0,0 +1,7 @@
+fn greeting() -> &'static str {
+    "hello"
+}
+
+fn main() {
+    println!("{}", greeting());
+}
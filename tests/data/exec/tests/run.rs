@@ -0,0 +1,7 @@
+use std::process::Command;
+
+#[test]
+fn runs_binary() {
+    let output = Command::new(env!("CARGO_BIN_EXE_exec")).output().unwrap();
+    assert_eq!(output.stdout, b"hello\n");
+}
@@ -0,0 +1,34 @@
+pub fn in_child(x: u64) -> u64 {
+    x + 1
+}
+
+pub fn in_parent(x: u64) -> u64 {
+    x + 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern "C" {
+        fn fork() -> i32;
+        fn waitpid(pid: i32, status: *mut i32, options: i32) -> i32;
+        fn _exit(status: i32) -> !;
+    }
+
+    #[test]
+    fn forked_hits() {
+        let pid = unsafe { fork() };
+        assert!(pid >= 0);
+        if pid == 0 {
+            let x = (0..3).fold(0, |acc, _| in_child(acc));
+            unsafe { _exit(if x == 3 { 0 } else { 1 }) }
+        }
+        let mut status = 0;
+        unsafe {
+            waitpid(pid, &mut status, 0);
+        }
+        assert_eq!(status, 0);
+        assert_eq!(in_parent(1), 3);
+    }
+}
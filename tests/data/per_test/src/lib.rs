@@ -0,0 +1,22 @@
+pub fn double(x: u64) -> u64 {
+    x * 2
+}
+
+pub fn halve(x: u64) -> u64 {
+    x / 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn doubles() {
+        assert_eq!(double(2), 4);
+    }
+
+    #[test]
+    fn halves() {
+        assert_eq!(halve(4), 2);
+    }
+}
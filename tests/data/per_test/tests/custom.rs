@@ -0,0 +1,3 @@
+fn main() {
+    assert_eq!(per_test::double(3), 6);
+}
@@ -0,0 +1,13 @@
+pub fn triple(x: u64) -> u64 {
+    x * 3
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn triples() {
+        assert_eq!(triple(2), 6);
+    }
+}
@@ -0,0 +1,12 @@
+fn in_example(x: u64) -> u64 {
+    x + 3
+}
+
+fn main() {
+    println!("{}", in_example(1));
+}
+
+#[test]
+fn example_test() {
+    assert_eq!(in_example(1), 4);
+}
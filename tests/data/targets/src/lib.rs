@@ -0,0 +1,8 @@
+pub fn in_lib(x: u64) -> u64 {
+    x + 1
+}
+
+#[test]
+fn lib_test() {
+    assert_eq!(in_lib(1), 2);
+}
@@ -0,0 +1,12 @@
+fn in_bin(x: u64) -> u64 {
+    x + 2
+}
+
+fn main() {
+    println!("{}", in_bin(1));
+}
+
+#[test]
+fn bin_test() {
+    assert_eq!(in_bin(1), 3);
+}
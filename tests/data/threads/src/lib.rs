@@ -0,0 +1,24 @@
+pub fn increment(x: u64) -> u64 {
+    x + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn threaded_hits() {
+        let handles = (0..4)
+            .map(|_| thread::spawn(|| (0..100).fold(0, |acc, _| increment(acc))))
+            .collect::<Vec<_>>();
+        for h in handles {
+            assert_eq!(h.join().unwrap(), 100);
+        }
+    }
+
+    #[test]
+    fn more_hits() {
+        assert_eq!((0..100).fold(0, |acc, _| increment(acc)), 100);
+    }
+}
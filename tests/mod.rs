@@ -1,9 +1,21 @@
 use cargo_tarpaulin::config::Config;
 use cargo_tarpaulin::launch_tarpaulin;
+use cargo_tarpaulin::traces::{CoverageStat, TraceMap};
 use std::env;
+use std::fs::{read_dir, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
-pub fn check_percentage(project_name: &str, minimum_coverage: f64, has_lines: bool) {
+/// ELF type of a position independent executable
+const ET_DYN: u8 = 3;
+
+/// Runs tarpaulin on a project in tests/data with the config changed by
+/// `setup`. Returns the coverage and the directory of the project
+pub fn get_coverage<F>(project_name: &str, setup: F) -> (TraceMap, PathBuf)
+where
+    F: FnOnce(&mut Config),
+{
     let mut config = Config::default();
     config.verbose = true;
     config.test_timeout = Duration::from_secs(60);
@@ -15,16 +27,40 @@ pub fn check_percentage(project_name: &str, minimum_coverage: f64, has_lines: bo
     env::set_current_dir(test_dir.clone()).unwrap();
     config.manifest = test_dir.clone();
     config.manifest.push("Cargo.toml");
+    setup(&mut config);
 
-    let res = launch_tarpaulin(&config).unwrap();
+    let res = launch_tarpaulin(&config);
 
     env::set_current_dir(restore_dir).unwrap();
+    (res.unwrap(), test_dir)
+}
+
+pub fn check_percentage(project_name: &str, minimum_coverage: f64, has_lines: bool) {
+    let (res, _) = get_coverage(project_name, |_| {});
     assert!(res.coverage_percentage() >= minimum_coverage);
     if has_lines {
         assert!(res.total_coverable() > 0);
     }
 }
 
+/// Gets the line coverage of a line in a file of the project
+pub fn line_stats(res: &TraceMap, file: &Path, line: u64) -> CoverageStat {
+    res.get_child_traces(file)
+        .iter()
+        .find(|x| x.line == line)
+        .map(|x| x.stats.clone())
+        .unwrap()
+}
+
+/// Gets how often the function whose name ends with `name` was entered
+pub fn function_hits(res: &TraceMap, file: &Path, name: &str) -> u64 {
+    res.functions_in_file(file)
+        .iter()
+        .find(|x| x.name.ends_with(name))
+        .map(|x| x.hits)
+        .unwrap()
+}
+
 #[test]
 fn incorrect_manifest_path() {
     let mut config = Config::default();
@@ -78,3 +114,101 @@ fn loops_expr_coverage() {
 fn loops_assigns_coverage() {
     check_percentage("assigns", 1.0f64, true);
 }
+
+#[test]
+fn threaded_breakpoint_hits() {
+    let (res, dir) = get_coverage("threads", |config| config.count = true);
+    // The tests call increment 500 times from several threads
+    assert_eq!(
+        function_hits(&res, &dir.join("src/lib.rs"), "increment"),
+        500
+    );
+}
+
+#[test]
+fn parallel_breakpoint_hits() {
+    let (res, dir) = get_coverage("threads", |config| {
+        config.count = true;
+        config.parallel = true;
+    });
+    assert_eq!(
+        function_hits(&res, &dir.join("src/lib.rs"), "increment"),
+        500
+    );
+}
+
+#[test]
+fn per_test_attribution() {
+    let (res, dir) = get_coverage("per_test", |config| config.per_test = true);
+    let tests = res.tests_in_file(&dir.join("src/lib.rs")).unwrap();
+    let names = |line| {
+        tests
+            .get(&line)
+            .map(|x| x.iter().map(|x| x.as_str()).collect::<Vec<_>>())
+            .unwrap_or_default()
+    };
+    // The custom harness can't list its tests so the whole binary is run
+    assert_eq!(names(2), vec!["custom", "per_test::tests::doubles"]);
+    assert_eq!(names(6), vec!["per_test::tests::halves"]);
+}
+
+#[test]
+fn doctest_coverage() {
+    let (res, dir) = get_coverage("doctests", |config| config.doc = true);
+    let stats = line_stats(&res, &dir.join("src/lib.rs"), 7);
+    assert_eq!(stats, CoverageStat::Line(1));
+}
+
+#[test]
+fn examples_with_default_targets() {
+    let (res, dir) = get_coverage("targets", |config| config.examples = true);
+    // The unit tests of the library and binary still run with the examples
+    for file in &["src/lib.rs", "src/main.rs", "examples/example.rs"] {
+        let stats = line_stats(&res, &dir.join(file), 2);
+        assert_eq!(stats, CoverageStat::Line(1), "{}", file);
+    }
+}
+
+#[test]
+fn follows_workspace_binary() {
+    let (res, dir) = get_coverage("exec", |_| {});
+    // Only the binary run by the integration test covers these lines
+    for &line in &[2, 6] {
+        let stats = line_stats(&res, &dir.join("src/main.rs"), line);
+        assert_eq!(stats, CoverageStat::Line(1));
+    }
+}
+
+#[test]
+fn forked_process_hits() {
+    let (res, dir) = get_coverage("fork", |config| config.count = true);
+    let file = dir.join("src/lib.rs");
+    // Hits in the forked child are counted once it exits
+    assert_eq!(function_hits(&res, &file, "in_child"), 3);
+    assert_eq!(function_hits(&res, &file, "in_parent"), 1);
+}
+
+#[test]
+fn pie_coverage() {
+    let (res, dir) = get_coverage("pie", |_| {});
+    let stats = line_stats(&res, &dir.join("src/lib.rs"), 2);
+    assert_eq!(stats, CoverageStat::Line(1));
+
+    // The test executable is linked as a PIE by default
+    let test_exe = read_dir(dir.join("target/debug/deps"))
+        .unwrap()
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .find(|p| {
+            let name = p.file_name().unwrap().to_string_lossy();
+            name.starts_with("pie-") && p.extension().is_none()
+        })
+        .unwrap();
+    let mut header = [0u8; 18];
+    File::open(test_exe)
+        .unwrap()
+        .read_exact(&mut header)
+        .unwrap();
+    assert_eq!(&header[..4], b"\x7fELF");
+    assert_eq!(header[16], ET_DYN);
+}